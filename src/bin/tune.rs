@@ -0,0 +1,9 @@
+//! The Texel tuner on its own: `tune <dataset> [--output path] [--params path]
+//! [--iterations n] [--rate r]`, the same as `crab-gambit tune`.
+
+use std::env;
+
+fn main() -> Result<(), crab_gambit::Error> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    crab_gambit::tune::run(&args)
+}
@@ -1,69 +1,278 @@
 use std::fmt::Display;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not, Sub};
 
 use crate::square::{ Square, RANK_NAMES, FILE_NAMES };
 
 const FIRST_RANK: u64 = 0x00000000000000FF;
 const FIRST_FILE: u64 = 0x0101010101010101;
+const NOT_A_FILE: u64 = !FIRST_FILE;
+const NOT_H_FILE: u64 = !(FIRST_FILE << 7);
 
-#[derive(Clone, Copy, PartialEq)]
+/// One bit per square, bit `i` standing for `Square::from_index(i)`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Bitboard {
     bits: u64
 }
 
 impl Bitboard {
-    pub fn empty() -> Bitboard {
-        Bitboard { bits: 0 }
+    pub const EMPTY: Bitboard = Bitboard::from_bits(0);
+    pub const FULL: Bitboard = Bitboard::from_bits(!0);
+
+    pub const fn empty() -> Bitboard {
+        Bitboard::EMPTY
+    }
+
+    pub const fn from_bits(bits: u64) -> Bitboard {
+        Bitboard { bits }
     }
 
-    pub fn rank(r: i8) -> Bitboard {
+    pub const fn from_square(square: Square) -> Bitboard {
+        Bitboard::from_bits(1 << square.index())
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.bits
+    }
+
+    /// Every square of rank `r`, or nothing if `r` is off the board.
+    pub const fn rank(r: i8) -> Bitboard {
         if r >= 0 && r < 8 {
-            Bitboard { bits: FIRST_RANK >> (8 * r) }
+            Bitboard::from_bits(FIRST_RANK << (8 * r))
         } else {
-            Bitboard::empty()
+            Bitboard::EMPTY
         }
     }
 
-    pub fn file(f: i8) -> Bitboard {
+    /// Every square of file `f`, or nothing if `f` is off the board.
+    pub const fn file(f: i8) -> Bitboard {
         if f >= 0 && f < 8 {
-            Bitboard { bits: FIRST_FILE >> f }
+            Bitboard::from_bits(FIRST_FILE << f)
         } else {
-            Bitboard::empty()
+            Bitboard::EMPTY
         }
     }
 
-    pub fn count(&self) -> u32 {
+    pub const fn count(&self) -> u32 {
         self.bits.count_ones()
     }
 
+    pub const fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
     pub fn set(&mut self, square: Square, value: bool) {
-        if square.is_valid() {
-            let mask = 1 << (square.file * 8 + square.rank);
-            if value {
-                self.bits |= mask;
-            } else {
-                self.bits &= !mask;
-            }
+        let mask = 1 << square.index();
+        if value {
+            self.bits |= mask;
+        } else {
+            self.bits &= !mask;
         }
     }
 
-    pub fn get(&self, square: Square) -> bool {
-        if square.is_valid() {
-            self.bits & (1 << (square.file * 8 + square.rank)) != 0
+    pub const fn get(&self, square: Square) -> bool {
+        self.bits & (1 << square.index()) != 0
+    }
+
+    pub const fn invert(&self) -> Bitboard {
+        Bitboard::from_bits(!self.bits)
+    }
+
+    pub const fn intersect(&self, other: Bitboard) -> Bitboard {
+        Bitboard::from_bits(self.bits & other.bits)
+    }
+
+    pub const fn union(&self, other: Bitboard) -> Bitboard {
+        Bitboard::from_bits(self.bits | other.bits)
+    }
+
+    /// The lowest square, a1 first and h8 last.
+    pub const fn lsb(&self) -> Option<Square> {
+        if self.bits == 0 {
+            None
         } else {
-            false
+            Some(Square::from_index(self.bits.trailing_zeros() as usize))
+        }
+    }
+
+    /// Removes and returns the lowest square.
+    pub fn pop_lsb(&mut self) -> Option<Square> {
+        let square = self.lsb()?;
+        self.bits &= self.bits - 1;
+        Some(square)
+    }
+
+    pub const fn north(self) -> Bitboard {
+        Bitboard::from_bits(self.bits << 8)
+    }
+
+    pub const fn south(self) -> Bitboard {
+        Bitboard::from_bits(self.bits >> 8)
+    }
+
+    pub const fn east(self) -> Bitboard {
+        Bitboard::from_bits((self.bits & NOT_H_FILE) << 1)
+    }
+
+    pub const fn west(self) -> Bitboard {
+        Bitboard::from_bits((self.bits & NOT_A_FILE) >> 1)
+    }
+
+    pub const fn north_east(self) -> Bitboard {
+        Bitboard::from_bits((self.bits & NOT_H_FILE) << 9)
+    }
+
+    pub const fn north_west(self) -> Bitboard {
+        Bitboard::from_bits((self.bits & NOT_A_FILE) << 7)
+    }
+
+    pub const fn south_east(self) -> Bitboard {
+        Bitboard::from_bits((self.bits & NOT_H_FILE) >> 7)
+    }
+
+    pub const fn south_west(self) -> Bitboard {
+        Bitboard::from_bits((self.bits & NOT_A_FILE) >> 9)
+    }
+
+    /// One rank towards the other side: north for white, south for black.
+    pub const fn forward(self, is_white: bool) -> Bitboard {
+        if is_white { self.north() } else { self.south() }
+    }
+
+    /// These squares and every square above them.
+    pub const fn north_fill(self) -> Bitboard {
+        let mut bits = self.bits;
+        bits |= bits << 8;
+        bits |= bits << 16;
+        bits |= bits << 32;
+        Bitboard::from_bits(bits)
+    }
+
+    /// These squares and every square below them.
+    pub const fn south_fill(self) -> Bitboard {
+        let mut bits = self.bits;
+        bits |= bits >> 8;
+        bits |= bits >> 16;
+        bits |= bits >> 32;
+        Bitboard::from_bits(bits)
+    }
+
+    /// Every file with at least one of these squares.
+    pub const fn file_fill(self) -> Bitboard {
+        self.north_fill().union(self.south_fill())
+    }
+
+    /// The squares strictly between `a` and `b` if they share a rank, file or
+    /// diagonal, otherwise nothing.
+    pub fn between(a: Square, b: Square) -> Bitboard {
+        BETWEEN[a.index()][b.index()]
+    }
+
+    /// The whole rank, file or diagonal through `a` and `b`, edge to edge, or
+    /// nothing if they are not on one.
+    pub fn line(a: Square, b: Square) -> Bitboard {
+        LINE[a.index()][b.index()]
+    }
+}
+
+const DIRECTIONS: [(i8, i8); 8] = [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+
+/// The squares from `square` towards the edge in direction `dir`, excluding `square`.
+const fn ray(square: Square, dir: usize) -> u64 {
+    let (dr, df) = DIRECTIONS[dir];
+    let mut bits = 0;
+    let mut current = square.offset(dr, df);
+    while let Some(next) = current {
+        bits |= 1 << next.index();
+        current = next.offset(dr, df);
+    }
+    bits
+}
+
+/// For every pair of squares on a common line, the squares strictly between
+/// them, or with `whole_line` the full line through them.
+const fn build_table(whole_line: bool) -> [[Bitboard; 64]; 64] {
+    let mut table = [[Bitboard::EMPTY; 64]; 64];
+    let mut a = 0;
+    while a < 64 {
+        let square = Square::from_index(a);
+        let mut dir = 0;
+        while dir < 8 {
+            let (dr, df) = DIRECTIONS[dir];
+            // The opposite direction is four steps further round
+            let full = ray(square, dir) | ray(square, (dir + 4) % 8) | 1 << a;
+            let mut passed = 0;
+            let mut current = square.offset(dr, df);
+            while let Some(b) = current {
+                table[a][b.index()] = Bitboard::from_bits(if whole_line { full } else { passed });
+                passed |= 1 << b.index();
+                current = b.offset(dr, df);
+            }
+            dir += 1;
         }
+        a += 1;
+    }
+    table
+}
+
+static BETWEEN: [[Bitboard; 64]; 64] = build_table(false);
+static LINE: [[Bitboard; 64]; 64] = build_table(true);
+
+impl BitAnd for Bitboard {
+    type Output = Bitboard;
+
+    fn bitand(self, other: Bitboard) -> Bitboard {
+        self.intersect(other)
+    }
+}
+
+impl BitOr for Bitboard {
+    type Output = Bitboard;
+
+    fn bitor(self, other: Bitboard) -> Bitboard {
+        self.union(other)
+    }
+}
+
+impl BitXor for Bitboard {
+    type Output = Bitboard;
+
+    fn bitxor(self, other: Bitboard) -> Bitboard {
+        Bitboard::from_bits(self.bits ^ other.bits)
+    }
+}
+
+/// The squares of `self` that are not in `other`.
+impl Sub for Bitboard {
+    type Output = Bitboard;
+
+    fn sub(self, other: Bitboard) -> Bitboard {
+        Bitboard::from_bits(self.bits & !other.bits)
+    }
+}
+
+impl Not for Bitboard {
+    type Output = Bitboard;
+
+    fn not(self) -> Bitboard {
+        self.invert()
     }
+}
 
-    pub fn invert(&self) -> Bitboard {
-        return Bitboard { bits: !self.bits }
+impl BitAndAssign for Bitboard {
+    fn bitand_assign(&mut self, other: Bitboard) {
+        self.bits &= other.bits;
     }
+}
 
-    pub fn intersect(&self, other: Bitboard) -> Bitboard {
-        Bitboard { bits: self.bits & other.bits }
+impl BitOrAssign for Bitboard {
+    fn bitor_assign(&mut self, other: Bitboard) {
+        self.bits |= other.bits;
     }
+}
 
-    pub fn union(&self, other: Bitboard) -> Bitboard {
-        Bitboard { bits: self.bits | other.bits }
+impl BitXorAssign for Bitboard {
+    fn bitxor_assign(&mut self, other: Bitboard) {
+        self.bits ^= other.bits;
     }
 }
 
@@ -72,7 +281,7 @@ impl Display for Bitboard {
         for rank in (0..8).rev() {
             write!(f, "{}  ", RANK_NAMES[rank as usize])?;
             for file in 0..8 {
-                if self.get(Square { rank, file }) {
+                if self.get(Square::new(rank, file)) {
                     write!(f, " X")?;
                 } else {
                     write!(f, " .")?;
@@ -82,8 +291,8 @@ impl Display for Bitboard {
         }
         writeln!(f)?;
         write!(f, "   ")?;
-        for file in 0..8 {
-            write!(f, " {}", FILE_NAMES[file])?;
+        for name in FILE_NAMES {
+            write!(f, " {}", name)?;
         }
         Ok(())
     }
@@ -91,9 +300,7 @@ impl Display for Bitboard {
 
 impl From<Square> for Bitboard {
     fn from(square: Square) -> Self {
-        let mut result = Bitboard::empty();
-        result.set(square, true);
-        return result;
+        Bitboard::from_square(square)
     }
 }
 
@@ -102,26 +309,18 @@ impl IntoIterator for Bitboard {
     type IntoIter = BitboardIterator;
 
     fn into_iter(self) -> Self::IntoIter {
-        BitboardIterator { bits: self.bits }
+        BitboardIterator { bits: self }
     }
 }
 
 pub struct BitboardIterator {
-    bits: u64
+    bits: Bitboard
 }
 
 impl Iterator for BitboardIterator {
     type Item = Square;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.bits == 0 {
-            return None;
-        }
-        let index = self.bits.trailing_zeros();
-        self.bits ^= 1 << index;
-        return Some(Square {
-            rank: (index % 8) as i8,
-            file: (index / 8) as i8
-        });
-    }
-}
\ No newline at end of file
+        self.bits.pop_lsb()
+    }
+}
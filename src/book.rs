@@ -0,0 +1,100 @@
+use std::fs;
+
+use rand::Rng;
+
+use crate::error::Error;
+use crate::moves::{generate_legal_moves, Move, MoveList};
+use crate::piece::PieceType;
+use crate::position::Position;
+use crate::square::Square;
+
+/// One 16 byte record of a Polyglot book, stored big-endian.
+#[derive(Clone, Copy)]
+struct BookEntry {
+    key: u64,
+    /// Bits 0-5 destination, 6-11 source (`rank * 8 + file`), 12-14 promotion
+    mv: u16,
+    weight: u16
+}
+
+/// A Polyglot `.bin` opening book, entries sorted by key.
+pub struct Book {
+    entries: Vec<BookEntry>
+}
+
+impl Book {
+    pub fn load(path: &str) -> Result<Book, Error> {
+        let bytes = fs::read(path).map_err(|e| Error::Io(format!("Cannot read book {}: {}", path, e)))?;
+        if bytes.len() % 16 != 0 {
+            return Err(Error::Parse(format!("Book {} is not a Polyglot book", path)));
+        }
+        let entries = bytes.chunks_exact(16).map(|chunk| BookEntry {
+            key: u64::from_be_bytes(chunk[0..8].try_into().unwrap()),
+            mv: u16::from_be_bytes(chunk[8..10].try_into().unwrap()),
+            weight: u16::from_be_bytes(chunk[10..12].try_into().unwrap())
+        }).collect();
+        Ok(Book { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Picks one of the book moves for `pos` with probability proportional
+    /// to its weight, or `None` if the position is not in the book.
+    pub fn probe(&self, pos: &Position, rng: &mut impl Rng) -> Option<Move> {
+        let key = pos.polyglot_key();
+        let start = self.entries.partition_point(|e| e.key < key);
+        let candidates: Vec<BookEntry> = self.entries[start..].iter()
+            .take_while(|e| e.key == key)
+            .copied()
+            .collect();
+        let total: u32 = candidates.iter().map(|e| e.weight as u32).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut pick = rng.random_range(0..total);
+        let entry = candidates.iter().find(|e| {
+            if pick < e.weight as u32 {
+                return true;
+            }
+            pick -= e.weight as u32;
+            false
+        })?;
+
+        // Only play moves that are legal here, in case of a key collision
+        let mv = decode_move(pos, entry.mv);
+        let mut moves = MoveList::new();
+        generate_legal_moves(&mut moves, &mut pos.snapshot()).ok()?;
+        moves.into_iter().find(|&m| m.same_squares(mv))
+    }
+}
+
+fn decode_move(pos: &Position, mv: u16) -> Move {
+    let square = |bits: u16| Square::new(((bits >> 3) & 7) as i8, (bits & 7) as i8);
+    let src = square(mv >> 6);
+    let mut dest = square(mv);
+    let promotion = match (mv >> 12) & 7 {
+        1 => Some(PieceType::Knight),
+        2 => Some(PieceType::Bishop),
+        3 => Some(PieceType::Rook),
+        4 => Some(PieceType::Queen),
+        _ => None
+    };
+
+    // Castling is stored as the king capturing its own rook, which is how we
+    // write it too in Chess960
+    if !pos.chess960 && pos.get_piece_type(src) == Some(PieceType::King)
+        && pos.by_color(pos.white_to_play).get(dest) {
+        dest = Square::new(dest.rank(), if dest.file() > src.file() { 6 } else { 2 });
+    }
+    match promotion {
+        Some(p_type) => Move::promotion_to(src, dest, p_type, false),
+        None => Move::quiet(src, dest)
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::error::Error;
+use crate::moves::{generate_legal_moves, MoveList};
+use crate::params::EvalParams;
+use crate::position::Position;
+use crate::search::{search, SearchContext, SearchLimits};
+use crate::square::Square;
+
+/// Games running longer than this many plies are adjudicated as draws.
+const MAX_GAME_PLIES: u32 = 400;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Binary
+}
+
+#[derive(Clone)]
+struct DatagenOptions {
+    output: String,
+    format: Format,
+    games: u32,
+    threads: usize,
+    nodes: u32,
+    random_plies: u32,
+    /// Start from random Chess960 positions rather than the standard one
+    chess960: bool,
+    seed: u64,
+    params: EvalParams
+}
+
+fn parse_options(args: &[String]) -> Result<DatagenOptions, Error> {
+    let mut options = DatagenOptions {
+        output: String::new(),
+        format: Format::Text,
+        games: 100,
+        threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        nodes: 5000,
+        random_plies: 8,
+        chess960: false,
+        seed: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        params: EvalParams::default()
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or(Error::Parse(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--format" => options.format = match value()?.as_str() {
+                "text" => Format::Text,
+                "binary" => Format::Binary,
+                f => return Err(Error::Parse(format!("Unknown format '{}', expected text or binary", f)))
+            },
+            "--games" => options.games = value()?.parse().map_err(|_| Error::Parse(String::from("Invalid game count")))?,
+            "--threads" => options.threads = value()?.parse().map_err(|_| Error::Parse(String::from("Invalid thread count")))?,
+            "--nodes" => options.nodes = value()?.parse().map_err(|_| Error::Parse(String::from("Invalid node count")))?,
+            "--random-plies" => options.random_plies = value()?.parse().map_err(|_| Error::Parse(String::from("Invalid ply count")))?,
+            "--chess960" => options.chess960 = true,
+            "--seed" => options.seed = value()?.parse().map_err(|_| Error::Parse(String::from("Invalid seed")))?,
+            "--params" => options.params = EvalParams::load(&value()?)?,
+            _ if options.output.is_empty() => options.output = arg.clone(),
+            _ => return Err(Error::Parse(format!("Unexpected argument '{}'", arg)))
+        }
+    }
+    if options.output.is_empty() {
+        return Err(Error::Parse(String::from("Usage: datagen <output> [--format text|binary] [--games n] [--threads n] \
+            [--nodes n] [--random-plies n] [--chess960] [--seed n] [--params path]")));
+    }
+    Ok(options)
+}
+
+/// A position worth learning from, with the search score in centipawns from white's point of view.
+struct Record {
+    pos: Position,
+    score: i16
+}
+
+/// Writes `<fen> | <score> | <result>`, with the result 1.0, 0.5 or 0.0 from white's point of view.
+fn write_text(out: &mut impl Write, record: &Record, result: f32) -> std::io::Result<()> {
+    writeln!(out, "{} | {} | {:.1}", record.pos.to_fen(), record.score, result)
+}
+
+/// Writes a fixed 32 byte record:
+///
+/// - bytes 0-7: occupancy as a little-endian u64, bit `rank * 8 + file`
+/// - bytes 8-23: one nibble per occupied square in occupancy order, low
+///   nibble first; bit 3 is set for black pieces and the low bits are the
+///   piece type from pawn (0) to king (5)
+/// - byte 24: bit 0 set if white is to play, bits 1-4 castling rights KQkq
+/// - byte 25: file of the pawn that can be captured en passant, or 8
+/// - byte 26: halfmove clock, saturating at 255
+/// - bytes 27-28: fullmove number as a little-endian u16
+/// - bytes 29-30: score as a little-endian i16
+/// - byte 31: result, 0 for a black win, 1 for a draw and 2 for a white win
+fn write_binary(out: &mut impl Write, record: &Record, result: f32) -> std::io::Result<()> {
+    let pos = &record.pos;
+    let mut bytes = [0u8; 32];
+
+    let mut occupancy = 0u64;
+    let mut nibbles = 0usize;
+    for index in 0..64 {
+        let square = Square::from_index(index);
+        if let Some(piece) = pos.get_peice(square) {
+            occupancy |= 1 << index;
+            let nibble = piece.p_type as u8 | if piece.is_white { 0 } else { 8 };
+            bytes[8 + nibbles / 2] |= nibble << (4 * (nibbles % 2));
+            nibbles += 1;
+        }
+    }
+    bytes[0..8].copy_from_slice(&occupancy.to_le_bytes());
+
+    bytes[24] = pos.white_to_play as u8
+        | (pos.white_castle_state.can_short_castle as u8) << 1
+        | (pos.white_castle_state.can_long_castle as u8) << 2
+        | (pos.black_castle_state.can_short_castle as u8) << 3
+        | (pos.black_castle_state.can_long_castle as u8) << 4;
+    bytes[25] = pos.en_passant_target.map(|sq| sq.file() as u8).unwrap_or(8);
+    bytes[26] = pos.halfmove_clock.min(255) as u8;
+    bytes[27..29].copy_from_slice(&(pos.fullmove_number.min(u16::MAX as u32) as u16).to_le_bytes());
+    bytes[29..31].copy_from_slice(&record.score.to_le_bytes());
+    bytes[31] = (result * 2f32) as u8;
+    out.write_all(&bytes)
+}
+
+/// Placement, side, castling and en passant, the parts of a FEN that decide repetitions.
+fn repetition_key(pos: &Position) -> String {
+    pos.to_fen().split(' ').take(4).collect::<Vec<&str>>().join(" ")
+}
+
+fn legal_moves(pos: &Position) -> Result<MoveList, Error> {
+    let mut moves = MoveList::new();
+    generate_legal_moves(&mut moves, &mut pos.snapshot())?;
+    Ok(moves)
+}
+
+/// Plays random legal moves from the start position so games do not repeat.
+fn random_opening(rng: &mut StdRng, plies: u32, chess960: bool) -> Result<Position, Error> {
+    'retry: loop {
+        let mut pos = if chess960 { Position::start960(rng.random_range(0..960))? } else { Position::start() };
+        for _ in 0..plies {
+            let moves = legal_moves(&pos)?;
+            if moves.is_empty() {
+                continue 'retry;
+            }
+            pos.do_move(moves[rng.random_range(0..moves.len())])?;
+        }
+        if !legal_moves(&pos)?.is_empty() {
+            return Ok(pos);
+        }
+    }
+}
+
+/// Plays one self-play game, returning the quiet positions seen and the result from white's point of view.
+fn play_game(ctx: &mut SearchContext, rng: &mut StdRng, options: &DatagenOptions) -> Result<(Vec<Record>, f32), Error> {
+    let mut pos = random_opening(rng, options.random_plies, options.chess960)?;
+    let mut repetitions: HashMap<String, u32> = HashMap::new();
+    let mut records = Vec::new();
+
+    for _ in 0..MAX_GAME_PLIES {
+        let moves = legal_moves(&pos)?;
+        let in_check = pos.snapshot().is_check()?;
+        if moves.is_empty() {
+            let result = if !in_check { 0.5 } else if pos.white_to_play { 0.0 } else { 1.0 };
+            return Ok((records, result));
+        }
+
+        let count = repetitions.entry(repetition_key(&pos)).or_insert(0);
+        *count += 1;
+        if *count >= 3 || pos.halfmove_clock >= 100 || pos.is_insufficient_material() {
+            return Ok((records, 0.5));
+        }
+
+        let limits = SearchLimits { nodes: Some(options.nodes), ..SearchLimits::default() };
+        let result = search(ctx, &mut pos.snapshot(), &limits)?;
+        let score = result.score;
+        let white_score = if pos.white_to_play { score } else { -score };
+        if score.is_infinite() {
+            // A forced mate was found, no need to play it out
+            return Ok((records, if white_score > 0f32 { 1.0 } else { 0.0 }));
+        }
+        let mv = result.best_move.unwrap_or(moves[0]);
+
+        // Skip noisy positions, where the static evaluation cannot be trusted
+        if !in_check && !mv.is_capture() {
+            records.push(Record {
+                pos: pos.snapshot(),
+                score: (white_score * 100f32).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+            });
+        }
+
+        pos.do_move(mv)?;
+    }
+    Ok((records, 0.5))
+}
+
+/// Generates training data from fixed-node self-play games across several threads.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let options = parse_options(args)?;
+    let file = File::create(&options.output).map_err(|e| Error::Io(format!("Cannot create {}: {}", options.output, e)))?;
+    let mut out = BufWriter::new(file);
+
+    let (tx, rx) = mpsc::channel::<Result<(Vec<Record>, f32), Error>>();
+    let started = Arc::new(AtomicU32::new(0));
+    for id in 0..options.threads {
+        let tx = tx.clone();
+        let started = started.clone();
+        let options = options.clone();
+        thread::spawn(move || {
+            let mut ctx = SearchContext::new();
+            ctx.params = options.params.clone();
+            let mut rng = StdRng::seed_from_u64(options.seed.wrapping_add(id as u64));
+            while started.fetch_add(1, Ordering::Relaxed) < options.games {
+                if tx.send(play_game(&mut ctx, &mut rng, &options)).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    drop(tx);
+
+    let start_time = Instant::now();
+    let mut games = 0;
+    let mut positions = 0;
+    for game in rx {
+        let (records, result) = game?;
+        for record in &records {
+            match options.format {
+                Format::Text => write_text(&mut out, record, result),
+                Format::Binary => write_binary(&mut out, record, result)
+            }?;
+        }
+        games += 1;
+        positions += records.len();
+        if games % 10 == 0 || games == options.games {
+            let elapsed = start_time.elapsed().as_secs_f32();
+            println!("{} games, {} positions, {:.1} positions/s", games, positions, positions as f32 / elapsed);
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::bitboard::Bitboard;
+use crate::kpk;
+use crate::position::Position;
+use crate::square::Square;
+
+/// Added to scores of endgames that are known to be won, so the search heads for them.
+pub const KNOWN_WIN: f32 = 100.0;
+
+/// Specialized knowledge for a material signature. Both kinds take the
+/// position and whether the strong side is white.
+#[derive(Clone, Copy)]
+pub enum EndgameKind {
+    /// Replaces the evaluation with a score in pawns for the strong side
+    Value(fn(&Position, bool) -> f32),
+    /// Scales the strong side's advantage by a factor from 0 to 1
+    Scale(fn(&Position, bool) -> f32)
+}
+
+#[derive(Clone, Copy)]
+pub struct Endgame {
+    pub name: &'static str,
+    pub strong_is_white: bool,
+    pub kind: EndgameKind
+}
+
+/// Packs the count of every non-king piece of each color into four bits,
+/// identifying the material balance of a position.
+pub fn material_key(pos: &Position) -> u64 {
+    let mut key = 0u64;
+    let mut shift = 0;
+    for is_white in [true, false] {
+        let us = pos.by_color(is_white);
+        for pieces in [pos.pawns, pos.knights, pos.bishops, pos.rooks, pos.queens] {
+            key |= (pieces.intersect(us).count().min(15) as u64) << shift;
+            shift += 4;
+        }
+    }
+    key
+}
+
+/// Material key of a signature such as `KBNK`, where the first king starts
+/// the white pieces and the second king the black ones.
+pub fn signature_key(signature: &str) -> u64 {
+    let black_start = signature[1..].find('K').unwrap() + 1;
+    let mut key = 0u64;
+    for (i, c) in signature.chars().enumerate() {
+        let piece = match c {
+            'P' => 0,
+            'N' => 1,
+            'B' => 2,
+            'R' => 3,
+            'Q' => 4,
+            _ => continue
+        };
+        let shift = if i < black_start { piece * 4 } else { 20 + piece * 4 };
+        key += 1 << shift;
+    }
+    key
+}
+
+/// Swaps the two sides of a signature, turning `KRKP` into `KPKR`.
+fn mirror_signature(signature: &str) -> String {
+    let black_start = signature[1..].find('K').unwrap() + 1;
+    format!("{}{}", &signature[black_start..], &signature[..black_start])
+}
+
+fn endgames() -> &'static HashMap<u64, Endgame> {
+    static ENDGAMES: OnceLock<HashMap<u64, Endgame>> = OnceLock::new();
+    ENDGAMES.get_or_init(|| {
+        let mut endgames = HashMap::new();
+        let mut add = |name: &'static str, kind: EndgameKind| {
+            endgames.insert(signature_key(name), Endgame { name, strong_is_white: true, kind });
+            endgames.insert(signature_key(&mirror_signature(name)), Endgame { name, strong_is_white: false, kind });
+        };
+
+        add("KPK", EndgameKind::Value(evaluate_kpk));
+        add("KNK", EndgameKind::Value(evaluate_draw));
+        add("KBK", EndgameKind::Value(evaluate_draw));
+        add("KNNK", EndgameKind::Value(evaluate_draw));
+        add("KBNK", EndgameKind::Value(evaluate_kbnk));
+        add("KRK", EndgameKind::Value(evaluate_kxk));
+        add("KQK", EndgameKind::Value(evaluate_kxk));
+        add("KRKP", EndgameKind::Value(evaluate_krkp));
+        add("KBPK", EndgameKind::Scale(scale_kbpsk));
+        add("KBPPK", EndgameKind::Scale(scale_kbpsk));
+        add("KBPPPK", EndgameKind::Scale(scale_kbpsk));
+        endgames
+    })
+}
+
+/// Finds specialized knowledge for the material in `pos`, if there is any.
+pub fn probe(pos: &Position) -> Option<Endgame> {
+    endgames().get(&material_key(pos)).copied()
+}
+
+fn distance(a: Square, b: Square) -> i8 {
+    (a.rank() - b.rank()).abs().max((a.file() - b.file()).abs())
+}
+
+fn edge_distance(x: i8) -> i8 {
+    x.min(7 - x)
+}
+
+/// Larger the closer `sq` is to the edge of the board, from 0 to 0.9.
+fn push_to_edge(sq: Square) -> f32 {
+    let rd = edge_distance(sq.rank()) as f32;
+    let fd = edge_distance(sq.file()) as f32;
+    0.9 - 0.035 * (fd * fd + rd * rd)
+}
+
+/// Larger the closer two squares are, from 0 to 1.2.
+fn push_close(a: Square, b: Square) -> f32 {
+    1.4 - 0.2 * distance(a, b) as f32
+}
+
+fn square_of(pieces: Bitboard) -> Square {
+    pieces.into_iter().next().unwrap()
+}
+
+/// Flips ranks so the strong side always plays up the board.
+fn normalize(sq: Square, strong_is_white: bool) -> Square {
+    if strong_is_white { sq } else { sq.flip_rank() }
+}
+
+fn kings(pos: &Position, strong_is_white: bool) -> (Square, Square) {
+    (
+        square_of(pos.kings.intersect(pos.by_color(strong_is_white))),
+        square_of(pos.kings.intersect(pos.by_color(!strong_is_white)))
+    )
+}
+
+fn evaluate_draw(_pos: &Position, _strong_is_white: bool) -> f32 {
+    0f32
+}
+
+/// King and pawn against king, read from the bitbase.
+fn evaluate_kpk(pos: &Position, strong_is_white: bool) -> f32 {
+    let (strong_king, weak_king) = kings(pos, strong_is_white);
+    let pawn = normalize(square_of(pos.pawns), strong_is_white);
+    let strong_king = normalize(strong_king, strong_is_white);
+    let weak_king = normalize(weak_king, strong_is_white);
+    if kpk::probe(strong_king, pawn, weak_king, pos.white_to_play == strong_is_white) {
+        KNOWN_WIN + 1f32 + pawn.rank() as f32 * 0.1
+    } else {
+        0f32
+    }
+}
+
+/// Mating material against a lone king: drive the king to the edge and
+/// bring our own king closer.
+fn evaluate_kxk(pos: &Position, strong_is_white: bool) -> f32 {
+    let (strong_king, weak_king) = kings(pos, strong_is_white);
+    let us = pos.by_color(strong_is_white);
+    let material = pos.queens.intersect(us).count() as f32 * 9f32 + pos.rooks.intersect(us).count() as f32 * 5f32;
+    KNOWN_WIN + material + push_to_edge(weak_king) + push_close(strong_king, weak_king)
+}
+
+/// Bishop and knight: the king can only be mated in a corner of the bishop's color.
+fn evaluate_kbnk(pos: &Position, strong_is_white: bool) -> f32 {
+    let (strong_king, weak_king) = kings(pos, strong_is_white);
+    let bishop = square_of(pos.bishops);
+    // Distance from the a8-h1 diagonal, largest in the dark a1 and h8 corners
+    let light_bishop = (bishop.rank() + bishop.file()) % 2 == 1;
+    let file = if light_bishop { 7 - weak_king.file() } else { weak_king.file() };
+    let push_to_corner = (7 - weak_king.rank() - file).abs() as f32;
+    KNOWN_WIN + 6f32 + push_close(strong_king, weak_king) + 0.4 * push_to_corner
+}
+
+/// Rook against pawn. Won if our king gets in front of the pawn or the
+/// defending king is too far away, otherwise it depends on the race.
+fn evaluate_krkp(pos: &Position, strong_is_white: bool) -> f32 {
+    let (strong_king, weak_king) = kings(pos, strong_is_white);
+    let strong_king = normalize(strong_king, strong_is_white);
+    let weak_king = normalize(weak_king, strong_is_white);
+    let rook = normalize(square_of(pos.rooks), strong_is_white);
+    let pawn = normalize(square_of(pos.pawns), strong_is_white);
+    let queening = Square::new(0, pawn.file());
+    let strong_to_play = pos.white_to_play == strong_is_white;
+    let rook_value = 5f32;
+
+    if (strong_king.file() == pawn.file() && strong_king.rank() < pawn.rank())
+        || (distance(weak_king, pawn) >= 3 + !strong_to_play as i8 && distance(weak_king, rook) >= 3) {
+        rook_value - 0.08 * distance(strong_king, pawn) as f32
+    } else if weak_king.rank() <= 2 && distance(weak_king, pawn) == 1 && strong_king.rank() >= 3
+        && distance(strong_king, pawn) > 2 + strong_to_play as i8 {
+        0.8 - 0.08 * distance(strong_king, pawn) as f32
+    } else {
+        let in_front = Square::new(pawn.rank() - 1, pawn.file());
+        2f32 - 0.08 * (distance(strong_king, in_front) - distance(weak_king, in_front) - distance(pawn, queening)) as f32
+    }
+}
+
+/// Bishop and rook pawns: a draw if the bishop cannot control the queening
+/// square and the defending king has reached it.
+fn scale_kbpsk(pos: &Position, strong_is_white: bool) -> f32 {
+    let pawns = pos.pawns.intersect(pos.by_color(strong_is_white));
+    let file = square_of(pawns).file();
+    if (file != 0 && file != 7) || pawns.into_iter().any(|p| p.file() != file) {
+        return 1f32;
+    }
+
+    let (_, weak_king) = kings(pos, strong_is_white);
+    let queening = Square::new(if strong_is_white { 7 } else { 0 }, file);
+    let bishop = square_of(pos.bishops);
+    let same_color = (bishop.rank() + bishop.file()) % 2 == (queening.rank() + queening.file()) % 2;
+    if !same_color && distance(weak_king, queening) <= 1 {
+        0f32
+    } else {
+        1f32
+    }
+}
@@ -0,0 +1,451 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::moves::{generate_legal_moves, Move, MoveList};
+use crate::pgn::{read_games, Game, Node};
+use crate::position::Position;
+
+/// Extra time an engine may use before it loses on time, to cover process overhead.
+const TIME_MARGIN: Duration = Duration::from_millis(100);
+
+struct MatchOptions {
+    engines: Vec<String>,
+    openings: Option<String>,
+    games: u32,
+    /// Base time and increment per move
+    time: Duration,
+    increment: Duration,
+    engine_options: Vec<(String, String)>,
+    pgn: Option<String>,
+    /// Elo bounds for the SPRT, no test is run without them
+    sprt: Option<(f64, f64)>,
+    alpha: f64,
+    beta: f64
+}
+
+fn parse_options(args: &[String]) -> Result<MatchOptions, Error> {
+    let mut options = MatchOptions {
+        engines: Vec::new(),
+        openings: None,
+        games: 100,
+        time: Duration::from_secs(10),
+        increment: Duration::from_millis(100),
+        engine_options: Vec::new(),
+        pgn: None,
+        sprt: None,
+        alpha: 0.05,
+        beta: 0.05
+    };
+    let seconds = |s: &str| -> Result<Duration, Error> {
+        s.parse::<f64>().ok().filter(|s| *s >= 0.0).map(Duration::from_secs_f64).ok_or(Error::Parse(format!("Invalid time '{}'", s)))
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or(Error::Parse(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--openings" => options.openings = Some(value()?),
+            "--games" => options.games = value()?.parse().map_err(|_| Error::Parse(String::from("Invalid game count")))?,
+            "--tc" => {
+                let tc = value()?;
+                let (base, increment) = tc.split_once('+').unwrap_or((&tc, "0"));
+                options.time = seconds(base)?;
+                options.increment = seconds(increment)?;
+            },
+            "--option" => {
+                let option = value()?;
+                let (name, value) = option.split_once('=').ok_or(Error::Parse(format!("Expected Name=Value, got '{}'", option)))?;
+                options.engine_options.push((name.to_string(), value.to_string()));
+            },
+            "--pgn" => options.pgn = Some(value()?),
+            "--sprt" => {
+                let bounds = value()?;
+                let (elo0, elo1) = bounds.split_once(',').ok_or(Error::Parse(format!("Expected elo0,elo1, got '{}'", bounds)))?;
+                options.sprt = Some((
+                    elo0.parse().map_err(|_| Error::Parse(String::from("Invalid elo0")))?,
+                    elo1.parse().map_err(|_| Error::Parse(String::from("Invalid elo1")))?
+                ));
+            },
+            "--alpha" => options.alpha = value()?.parse().map_err(|_| Error::Parse(String::from("Invalid alpha")))?,
+            "--beta" => options.beta = value()?.parse().map_err(|_| Error::Parse(String::from("Invalid beta")))?,
+            _ if options.engines.len() < 2 => options.engines.push(arg.clone()),
+            _ => return Err(Error::Parse(format!("Unexpected argument '{}'", arg)))
+        }
+    }
+    if options.engines.len() != 2 {
+        return Err(Error::Parse(String::from("Usage: match <engine1> <engine2> [--openings file.epd|file.pgn] [--games n] \
+            [--tc base+inc] [--option Name=Value] [--pgn out.pgn] [--sprt elo0,elo1] [--alpha a] [--beta b]")));
+    }
+    Ok(options)
+}
+
+/// A UCI engine running as a subprocess. Its output is read on a separate
+/// thread so waiting for a move can time out.
+struct Engine {
+    name: String,
+    process: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>
+}
+
+impl Engine {
+    fn start(path: &str, options: &[(String, String)]) -> Result<Engine, Error> {
+        let mut process = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::Io(format!("Cannot start {}: {}", path, e)))?;
+        let stdin = process.stdin.take().ok_or(Error::Io(String::from("No engine stdin")))?;
+        let stdout = process.stdout.take().ok_or(Error::Io(String::from("No engine stdout")))?;
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut engine = Engine { name: path.to_string(), process, stdin, lines };
+        engine.send("uci")?;
+        while let Some(line) = engine.wait_for("uciok", Duration::from_secs(10))? {
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.to_string();
+            }
+        }
+        for (name, value) in options {
+            engine.send(&format!("setoption name {} value {}", name, value))?;
+        }
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), Error> {
+        writeln!(self.stdin, "{}", command).map_err(|e| Error::Io(format!("{} stopped: {}", self.name, e)))
+    }
+
+    /// Returns the lines before one starting with `prefix`, then `None` once
+    /// it arrives, or an error if it does not come within `timeout`.
+    fn wait_for(&mut self, prefix: &str, timeout: Duration) -> Result<Option<String>, Error> {
+        match self.lines.recv_timeout(timeout) {
+            Ok(line) if line.starts_with(prefix) => Ok(None),
+            Ok(line) => Ok(Some(line)),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Io(format!("{} did not send {} in time", self.name, prefix))),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::Io(format!("{} stopped", self.name)))
+        }
+    }
+
+    fn new_game(&mut self) -> Result<(), Error> {
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        while self.wait_for("readyok", Duration::from_secs(10))?.is_some() {}
+        Ok(())
+    }
+
+    /// Asks for a move, returning `None` if the engine runs out of time.
+    fn go(&mut self, position: &str, clocks: [Duration; 2], increment: Duration, white: bool) -> Result<Option<(String, Duration)>, Error> {
+        self.send(position)?;
+        self.send(&format!("go wtime {} btime {} winc {} binc {}",
+            clocks[0].as_millis(), clocks[1].as_millis(), increment.as_millis(), increment.as_millis()))?;
+        let start_time = Instant::now();
+        let deadline = start_time + clocks[if white { 0 } else { 1 }] + TIME_MARGIN;
+        loop {
+            let line = match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::Io(format!("{} stopped", self.name)))
+            };
+            if let Some(rest) = line.strip_prefix("bestmove ") {
+                let mv = rest.split_whitespace().next().unwrap_or("").to_string();
+                return Ok(Some((mv, start_time.elapsed())));
+            }
+        }
+
+        // Out of time, let it finish so the next game starts cleanly
+        self.send("stop")?;
+        while self.wait_for("bestmove", Duration::from_secs(10))?.is_some() {}
+        Ok(None)
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        // Give the engine a moment to exit on its own
+        for _ in 0..50 {
+            if let Ok(Some(_)) = self.process.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        let _ = self.process.kill();
+    }
+}
+
+/// Where a game starts: a position and the moves played from it.
+struct Opening {
+    pos: Position,
+    moves: Vec<Move>
+}
+
+fn load_openings(path: &str) -> Result<Vec<Opening>, Error> {
+    let text = fs::read_to_string(path).map_err(|e| Error::Io(format!("Cannot read {}: {}", path, e)))?;
+    if path.ends_with(".pgn") {
+        return read_games(&text)?.into_iter().map(|game| Ok(Opening {
+            pos: game.start_position()?,
+            moves: game.moves.iter().map(|node| node.mv).collect()
+        })).collect();
+    }
+    text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().take(4).collect();
+            Ok(Opening { pos: Position::from_fen(&format!("{} 0 1", fields.join(" ")))?, moves: Vec::new() })
+        })
+        .collect()
+}
+
+fn legal_moves(pos: &Position) -> Result<MoveList, Error> {
+    let mut moves = MoveList::new();
+    generate_legal_moves(&mut moves, &mut pos.snapshot())?;
+    Ok(moves)
+}
+
+/// How a game ended, from white's point of view.
+pub(crate) struct Outcome {
+    pub(crate) result: &'static str,
+    pub(crate) termination: String
+}
+
+impl Outcome {
+    pub(crate) fn win(white: bool, termination: String) -> Outcome {
+        Outcome { result: if white { "1-0" } else { "0-1" }, termination }
+    }
+
+    pub(crate) fn draw(termination: &str) -> Outcome {
+        Outcome { result: "1/2-1/2", termination: termination.to_string() }
+    }
+}
+
+/// Ends the game if the side to move is mated or the rules declare a draw.
+pub(crate) fn adjudicate(pos: &Position, keys: &[u64]) -> Result<Option<Outcome>, Error> {
+    if legal_moves(pos)?.is_empty() {
+        return Ok(Some(if pos.snapshot().is_check()? {
+            Outcome::win(!pos.white_to_play, String::from("checkmate"))
+        } else {
+            Outcome::draw("stalemate")
+        }));
+    }
+    // Only positions since the last capture or pawn move can repeat
+    let key = pos.polyglot_key();
+    let recent = &keys[keys.len().saturating_sub(pos.halfmove_clock as usize + 1)..];
+    if recent.iter().filter(|&&k| k == key).count() >= 3 {
+        return Ok(Some(Outcome::draw("threefold repetition")));
+    }
+    if pos.halfmove_clock >= 100 {
+        return Ok(Some(Outcome::draw("fifty-move rule")));
+    }
+    if pos.is_insufficient_material() {
+        return Ok(Some(Outcome::draw("insufficient material")));
+    }
+    Ok(None)
+}
+
+fn play_game(engines: &mut [Engine; 2], white: usize, opening: &Opening, options: &MatchOptions, round: u32) -> Result<Game, Error> {
+    let start_fen = opening.pos.to_fen();
+    let mut pos = opening.pos.snapshot();
+    let mut keys = vec![pos.polyglot_key()];
+    let mut game = Game::new();
+    for &mv in &opening.moves {
+        pos.do_move(mv)?;
+        keys.push(pos.polyglot_key());
+        game.moves.push(Node::new(mv));
+    }
+    for engine in engines.iter_mut() {
+        engine.new_game()?;
+    }
+
+    let mut clocks = [options.time; 2];
+    let outcome = loop {
+        if let Some(outcome) = adjudicate(&pos, &keys)? {
+            break outcome;
+        }
+
+        let side = if pos.white_to_play { 0 } else { 1 };
+        let engine = &mut engines[if pos.white_to_play { white } else { 1 - white }];
+        let moves: Vec<String> = game.moves.iter().map(|node| node.mv.to_string()).collect();
+        let command = if moves.is_empty() {
+            format!("position fen {}", start_fen)
+        } else {
+            format!("position fen {} moves {}", start_fen, moves.join(" "))
+        };
+        let Some((text, elapsed)) = engine.go(&command, clocks, options.increment, pos.white_to_play)? else {
+            break Outcome::win(!pos.white_to_play, format!("{} lost on time", engine.name));
+        };
+        let Ok(mv) = pos.parse_legal_move(&text) else {
+            break Outcome::win(!pos.white_to_play, format!("{} played illegal move {}", engine.name, text));
+        };
+        clocks[side] = clocks[side].saturating_sub(elapsed) + options.increment;
+
+        pos.do_move(mv)?;
+        keys.push(pos.polyglot_key());
+        game.moves.push(Node::new(mv));
+    };
+
+    let names = [&engines[white].name, &engines[1 - white].name];
+    game.tags = vec![
+        (String::from("Event"), String::from("Engine match")),
+        (String::from("Site"), String::from("?")),
+        (String::from("Date"), String::from("????.??.??")),
+        (String::from("Round"), round.to_string()),
+        (String::from("White"), names[0].clone()),
+        (String::from("Black"), names[1].clone()),
+        (String::from("Result"), outcome.result.to_string()),
+        (String::from("TimeControl"), format!("{}+{}", options.time.as_secs_f64(), options.increment.as_secs_f64())),
+        (String::from("Termination"), outcome.termination)
+    ];
+    if start_fen != Position::start().to_fen() {
+        game.tags.push((String::from("SetUp"), String::from("1")));
+        game.tags.push((String::from("FEN"), start_fen));
+    }
+    game.result = outcome.result.to_string();
+    Ok(game)
+}
+
+/// Converts an expected score into an Elo difference.
+fn elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/// Converts an Elo difference into an expected score.
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Wins, losses and draws of the first engine.
+#[derive(Default)]
+struct Tally {
+    wins: u32,
+    losses: u32,
+    draws: u32
+}
+
+impl Tally {
+    fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    fn score(&self) -> f64 {
+        (self.wins as f64 + 0.5 * self.draws as f64) / self.games() as f64
+    }
+
+    /// Variance of a single game's score around the mean.
+    fn variance(&self) -> f64 {
+        let score = self.score();
+        let n = self.games() as f64;
+        (self.wins as f64 * (1.0 - score).powi(2)
+            + self.losses as f64 * score.powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)) / n
+    }
+
+    /// Elo difference and its 95% error margin.
+    fn elo(&self) -> (f64, f64) {
+        let score = self.score();
+        let margin = 1.96 * (self.variance() / self.games() as f64).sqrt();
+        let low = elo((score - margin).max(1e-6));
+        let high = elo((score + margin).min(1.0 - 1e-6));
+        (elo(score.clamp(1e-6, 1.0 - 1e-6)), (high - low) / 2.0)
+    }
+
+    /// Log-likelihood ratio of elo1 against elo0 for the trinomial win, draw
+    /// and loss model, using the normal approximation. Outcomes that have not
+    /// happened yet count as half a game, which keeps the draw ratio below one
+    /// and the variance above zero after a one-sided start.
+    fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        if self.games() == 0 {
+            return 0.0;
+        }
+        let count = |n: u32| (n as f64).max(0.5);
+        let (wins, losses, draws) = (count(self.wins), count(self.losses), count(self.draws));
+        let n = wins + losses + draws;
+        let score = (wins + 0.5 * draws) / n;
+        let variance = (wins * (1.0 - score).powi(2) + losses * score.powi(2) + draws * (0.5 - score).powi(2)) / n;
+        let (s0, s1) = (expected_score(elo0), expected_score(elo1));
+        (s1 - s0) * (2.0 * n * score - n * (s0 + s1)) / (2.0 * variance)
+    }
+}
+
+/// `match <engine1> <engine2>`: plays the engines against each other, each
+/// opening twice with colors reversed, and reports the first engine's Elo.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let options = parse_options(args)?;
+    let openings = match &options.openings {
+        Some(path) => load_openings(path)?,
+        None => vec![Opening { pos: Position::start(), moves: Vec::new() }]
+    };
+    if openings.is_empty() {
+        return Err(Error::Parse(String::from("No openings found")));
+    }
+    let mut pgn = match &options.pgn {
+        Some(path) => Some(BufWriter::new(File::create(path).map_err(|e| Error::Io(format!("Cannot create {}: {}", path, e)))?)),
+        None => None
+    };
+
+    let mut engines = [
+        Engine::start(&options.engines[0], &options.engine_options)?,
+        Engine::start(&options.engines[1], &options.engine_options)?
+    ];
+    let bounds = ((options.beta / (1.0 - options.alpha)).ln(), ((1.0 - options.beta) / options.alpha).ln());
+    let mut tally = Tally::default();
+    for round in 0..options.games {
+        let opening = &openings[(round as usize / 2) % openings.len()];
+        let white = (round % 2) as usize;
+        let game = play_game(&mut engines, white, opening, &options, round + 1)?;
+        match (game.result.as_str(), white) {
+            ("1-0", 0) | ("0-1", 1) => tally.wins += 1,
+            ("1-0", 1) | ("0-1", 0) => tally.losses += 1,
+            _ => tally.draws += 1
+        }
+        if let Some(out) = pgn.as_mut() {
+            writeln!(out, "{}", game.to_pgn()?)?;
+            out.flush()?;
+        }
+        println!("Game {} {}: {} - {} - {} [{:.3}]  {}",
+            round + 1,
+            game.result,
+            tally.wins,
+            tally.losses,
+            tally.draws,
+            tally.score(),
+            game.tag("Termination").unwrap_or("")
+        );
+
+        if let Some((elo0, elo1)) = options.sprt {
+            let llr = tally.llr(elo0, elo1);
+            if llr <= bounds.0 || llr >= bounds.1 {
+                break;
+            }
+        }
+    }
+
+    let (diff, margin) = tally.elo();
+    println!();
+    println!("Score of {} vs {}: {} - {} - {} [{:.3}] {}",
+        engines[0].name, engines[1].name, tally.wins, tally.losses, tally.draws, tally.score(), tally.games());
+    println!("Elo difference: {:.1} +/- {:.1}", diff, margin);
+    if let Some((elo0, elo1)) = options.sprt {
+        let llr = tally.llr(elo0, elo1);
+        let verdict = if llr >= bounds.1 {
+            "H1 accepted"
+        } else if llr <= bounds.0 {
+            "H0 accepted"
+        } else {
+            "inconclusive"
+        };
+        println!("SPRT [{}, {}]: LLR {:.2} ({:.2}, {:.2}) {}", elo0, elo1, llr, bounds.0, bounds.1, verdict);
+    }
+    Ok(())
+}
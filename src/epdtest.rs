@@ -0,0 +1,226 @@
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::moves::Move;
+use crate::params::EvalParams;
+use crate::position::Position;
+use crate::search::{search, SearchContext, SearchLimits};
+
+struct EpdTestOptions {
+    path: String,
+    depth: Option<i32>,
+    time: Option<Duration>,
+    params: EvalParams
+}
+
+fn parse_options(args: &[String]) -> Result<EpdTestOptions, Error> {
+    let mut options = EpdTestOptions {
+        path: String::new(),
+        depth: None,
+        time: Some(Duration::from_secs(1)),
+        params: EvalParams::default()
+    };
+    let mut has_time = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or(Error::Parse(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--depth" => {
+                options.depth = Some(value()?.parse().map_err(|_| Error::Parse(String::from("Invalid depth")))?);
+            },
+            "--time" => {
+                options.time = Some(Duration::from_millis(value()?.parse().map_err(|_| Error::Parse(String::from("Invalid time")))?));
+                has_time = true;
+            },
+            "--params" => options.params = EvalParams::load(&value()?)?,
+            _ if options.path.is_empty() => options.path = arg.clone(),
+            _ => return Err(Error::Parse(format!("Unexpected argument '{}'", arg)))
+        }
+    }
+    if options.path.is_empty() {
+        return Err(Error::Parse(String::from("Usage: epdtest <file> [--depth n] [--time ms] [--params path]")));
+    }
+    // A depth alone searches to that depth however long it takes
+    if options.depth.is_some() && !has_time {
+        options.time = None;
+    }
+    Ok(options)
+}
+
+/// One test position with the opcodes we understand.
+struct EpdEntry {
+    pos: Position,
+    id: String,
+    best_moves: Vec<Move>,
+    avoid_moves: Vec<Move>,
+    /// STS style partial credit, the best move is worth 10 points
+    points: Vec<(Move, u32)>
+}
+
+/// Splits the operations after the FEN fields on `;`, keeping quoted
+/// operands together.
+fn split_operations(text: &str) -> Vec<(String, Vec<String>)> {
+    let mut operations = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut in_quotes = false;
+    for c in text.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' | ' ' | '\t' if !in_quotes => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                if c == ';' && !words.is_empty() {
+                    let opcode = words.remove(0);
+                    operations.push((opcode, std::mem::take(&mut words)));
+                }
+            },
+            _ => word.push(c)
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    if !words.is_empty() {
+        let opcode = words.remove(0);
+        operations.push((opcode, words));
+    }
+    operations
+}
+
+/// Splits the first word off `text`, returning it and the text after it.
+fn next_word(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    if end == 0 { None } else { Some((&text[..end], &text[end..])) }
+}
+
+fn parse_entry(line: &str, number: usize) -> Result<EpdEntry, Error> {
+    let mut fields = Vec::new();
+    let mut rest = line;
+    while fields.len() < 4 {
+        let (field, after) = next_word(rest).ok_or(Error::Parse(String::from("Not an EPD record")))?;
+        fields.push(field);
+        rest = after;
+    }
+    // A full FEN also has the halfmove clock and fullmove number, which no
+    // opcode can be mistaken for since opcodes start with a letter
+    let mut counters = Vec::new();
+    while counters.len() < 2
+        && let Some((field, after)) = next_word(rest)
+        && field.parse::<u32>().is_ok() {
+        counters.push(field);
+        rest = after;
+    }
+    let operations = split_operations(rest);
+    let operand = |name: &str| operations.iter().find(|(opcode, _)| opcode == name).map(|(_, operands)| operands);
+
+    let halfmove = counters.first().copied()
+        .or(operand("hmvc").and_then(|o| o.first()).map(|s| s.as_str()))
+        .unwrap_or("0");
+    let fullmove = counters.get(1).copied()
+        .or(operand("fmvn").and_then(|o| o.first()).map(|s| s.as_str()))
+        .unwrap_or("1");
+    let pos = Position::from_fen(&format!("{} {} {}", fields.join(" "), halfmove, fullmove))?;
+
+    let parse_moves = |name: &str| -> Result<Vec<Move>, Error> {
+        operand(name).map_or(Ok(Vec::new()), |moves| moves.iter().map(|san| pos.parse_san(san)).collect())
+    };
+    let best_moves = parse_moves("bm")?;
+    let avoid_moves = parse_moves("am")?;
+
+    // STS lists the credited moves in c0 as `Qd2=10, Qe1=5`
+    let mut points = Vec::new();
+    if let Some(credits) = operand("c0") {
+        for credit in credits.join(" ").split(',') {
+            if let Some((san, value)) = credit.trim().split_once('=')
+                && let Ok(value) = value.parse() {
+                points.push((pos.parse_san(san)?, value));
+            }
+        }
+    }
+
+    Ok(EpdEntry {
+        id: operand("id").map_or(format!("#{}", number), |o| o.join(" ")),
+        pos,
+        best_moves,
+        avoid_moves,
+        points
+    })
+}
+
+impl EpdEntry {
+    fn is_solved(&self, mv: Move) -> bool {
+        (self.best_moves.is_empty() || self.best_moves.contains(&mv)) && !self.avoid_moves.contains(&mv)
+    }
+
+    fn points(&self, mv: Move) -> u32 {
+        match self.points.iter().find(|(m, _)| *m == mv) {
+            Some((_, points)) => *points,
+            None if self.points.is_empty() && self.is_solved(mv) => 10,
+            None => 0
+        }
+    }
+
+    fn expected(&self) -> Result<String, Error> {
+        let format = |opcode: &str, moves: &[Move]| -> Result<String, Error> {
+            let sans: Result<Vec<String>, Error> = moves.iter().map(|&mv| self.pos.to_san(mv)).collect();
+            Ok(format!("{} {}", opcode, sans?.join(" ")))
+        };
+        let mut expected = Vec::new();
+        if !self.best_moves.is_empty() {
+            expected.push(format("bm", &self.best_moves)?);
+        }
+        if !self.avoid_moves.is_empty() {
+            expected.push(format("am", &self.avoid_moves)?);
+        }
+        Ok(expected.join("; "))
+    }
+}
+
+/// `epdtest <file>`: searches every position of an EPD suite and checks the
+/// chosen move against its `bm` and `am` opcodes, scoring STS suites by points.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let options = parse_options(args)?;
+    let text = fs::read_to_string(&options.path).map_err(|e| Error::Io(format!("Cannot read {}: {}", options.path, e)))?;
+    let entries = text.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| parse_entry(line.trim(), i + 1).map_err(|e| Error::Parse(format!("{} on line {}", e, i + 1))))
+        .collect::<Result<Vec<EpdEntry>, Error>>()?;
+
+    let mut ctx = SearchContext::new();
+    ctx.params = options.params;
+    let start_time = Instant::now();
+    let mut solved = 0;
+    let mut points = 0;
+    let limits = SearchLimits { depth: options.depth, time: options.time, ..SearchLimits::default() };
+    let mut nodes = 0u64;
+    for entry in &entries {
+        let result = search(&mut ctx, &mut entry.pos.snapshot(), &limits)?;
+        nodes += result.nodes;
+        let Some(mv) = result.best_move else {
+            println!("{:<24} failed  no move found ({})", entry.id, entry.expected()?);
+            continue;
+        };
+        let is_solved = entry.is_solved(mv);
+        solved += is_solved as usize;
+        points += entry.points(mv);
+        println!("{:<24} {}  {:<8} {:>2} pts  ({})",
+            entry.id,
+            if is_solved { "solved" } else { "failed" },
+            entry.pos.to_san(mv)?,
+            entry.points(mv),
+            entry.expected()?
+        );
+    }
+
+    let elapsed = start_time.elapsed();
+    let total = entries.len().max(1);
+    println!();
+    println!("Solved {}/{} ({:.1}%)", solved, entries.len(), 100.0 * solved as f32 / total as f32);
+    println!("Score {}/{} ({:.1}%)", points, 10 * entries.len(), 100.0 * points as f32 / (10 * total) as f32);
+    println!("Nodes {} in {:.1}s", nodes, elapsed.as_secs_f32());
+    Ok(())
+}
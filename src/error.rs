@@ -0,0 +1,75 @@
+use std::fmt::Display;
+
+use crate::square::Square;
+
+/// Errors from parsing notation and from working with positions and moves.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// Malformed text, such as a FEN, square, move or PGN game
+    Parse(String),
+    /// A move that cannot be played in the position
+    IllegalMove(String),
+    /// A position that does not follow the rules of chess
+    InvalidPosition(PositionError),
+    /// Reading or writing a file, or talking to another program
+    Io(String)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(message) | Error::IllegalMove(message) | Error::Io(message) => write!(f, "{}", message),
+            Error::InvalidPosition(error) => write!(f, "Invalid position: {}", error)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<PositionError> for Error {
+    fn from(error: PositionError) -> Self {
+        Error::InvalidPosition(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error.to_string())
+    }
+}
+
+/// What `Position::validate` found wrong with a position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PositionError {
+    /// A square is claimed by both colors or several piece types, or by a
+    /// color without a piece type
+    OverlappingPieces(Square),
+    PawnOnBackRank(Square),
+    MissingKing { white: bool },
+    TooManyKings { white: bool },
+    /// Castling rights without the king and rook on their home squares
+    ImpossibleCastlingRights { white: bool },
+    /// The en passant pawn is missing, or could not have just moved two squares
+    InvalidEnPassant(Square),
+    /// The side that just moved left its king in check
+    OpponentInCheck,
+    UnknownChess960Index(u32)
+}
+
+impl Display for PositionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let color = |white: bool| if white { "white" } else { "black" };
+        match self {
+            PositionError::OverlappingPieces(square) => write!(f, "square {} holds more than one piece", square),
+            PositionError::PawnOnBackRank(square) => write!(f, "pawn on back rank square {}", square),
+            PositionError::MissingKing { white } => write!(f, "{} has no king", color(*white)),
+            PositionError::TooManyKings { white } => write!(f, "{} has more than one king", color(*white)),
+            PositionError::ImpossibleCastlingRights { white } => write!(f, "{} cannot have its castling rights", color(*white)),
+            PositionError::InvalidEnPassant(square) => write!(f, "no pawn can be taken en passant on {}", square),
+            PositionError::OpponentInCheck => write!(f, "the side not to move is in check"),
+            PositionError::UnknownChess960Index(index) => write!(f, "Chess960 position {} is not between 0 and 959", index)
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
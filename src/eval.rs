@@ -0,0 +1,339 @@
+use std::fmt::Display;
+use std::ops::{Add, AddAssign, Neg, Sub};
+
+use crate::bitboard::Bitboard;
+use crate::endgame::{self, Endgame, EndgameKind};
+use crate::moves::{attacks, pawn_attacks};
+use crate::params::*;
+use crate::piece::{Piece, PieceType};
+use crate::position::Position;
+use crate::square::Square;
+
+/// A pair of midgame and endgame values, blended by game phase at the end of evaluation.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Score {
+    pub mg: f32,
+    pub eg: f32
+}
+
+impl Score {
+    pub const fn new(mg: f32, eg: f32) -> Score {
+        Score { mg, eg }
+    }
+
+    fn times(self, n: i32) -> Score {
+        Score::new(self.mg * n as f32, self.eg * n as f32)
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+
+    fn add(self, other: Score) -> Score {
+        Score::new(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, other: Score) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+
+    fn sub(self, other: Score) -> Score {
+        Score::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+
+    fn neg(self) -> Score {
+        Score::new(-self.mg, -self.eg)
+    }
+}
+
+// Phase contribution of each non-pawn piece; a full board of pieces is pure midgame.
+const KNIGHT_PHASE: u32 = 1;
+const BISHOP_PHASE: u32 = 1;
+const ROOK_PHASE: u32 = 2;
+const QUEEN_PHASE: u32 = 4;
+pub const MAX_PHASE: u32 = 24;
+
+/// Rows of the evaluation trace. There are no piece-square table or king
+/// safety rows because the evaluator has neither term yet.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Term {
+    Material,
+    Pawns,
+    Knights,
+    Bishops,
+    Rooks,
+    Mobility
+}
+
+const TERMS: [Term; 6] = [Term::Material, Term::Pawns, Term::Knights, Term::Bishops, Term::Rooks, Term::Mobility];
+
+impl Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Term::Material => "Material",
+            Term::Pawns => "Pawns",
+            Term::Knights => "Knights",
+            Term::Bishops => "Bishops",
+            Term::Rooks => "Rooks",
+            Term::Mobility => "Mobility"
+        })
+    }
+}
+
+/// Per-term breakdown of an evaluation, indexed by term and then by color (white first).
+///
+/// `coefficients` counts how often each parameter was applied, white uses
+/// minus black uses, so the white-side score is linear in the parameters.
+#[derive(Clone)]
+pub struct Trace {
+    terms: [[Score; 2]; TERMS.len()],
+    pub coefficients: [i32; PARAM_COUNT],
+    pub phase: u32,
+    pub white_to_play: bool,
+    /// Specialized endgame knowledge that overrides or scales the terms
+    pub endgame: Option<&'static str>,
+    /// Final evaluation from white's point of view
+    pub score: f32
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self {
+            terms: Default::default(),
+            coefficients: [0; PARAM_COUNT],
+            phase: 0,
+            white_to_play: true,
+            endgame: None,
+            score: 0f32
+        }
+    }
+}
+
+impl Trace {
+    fn add(&mut self, term: Term, is_white: bool, score: Score) {
+        self.terms[term as usize][if is_white { 0 } else { 1 }] += score;
+    }
+
+    pub fn get(&self, term: Term, is_white: bool) -> Score {
+        self.terms[term as usize][if is_white { 0 } else { 1 }]
+    }
+
+    /// Sum of all terms from white's point of view.
+    pub fn total(&self) -> Score {
+        TERMS.iter().fold(Score::default(), |acc, &t| acc + self.get(t, true) - self.get(t, false))
+    }
+
+    /// Final score in pawns from white's point of view.
+    pub fn tapered(&self) -> f32 {
+        taper(self.total(), self.phase)
+    }
+}
+
+fn write_score(f: &mut std::fmt::Formatter<'_>, score: Score) -> std::fmt::Result {
+    write!(f, " {:>6.2} {:>6.2} |", score.mg, score.eg)
+}
+
+impl Display for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "      Term |     White     |     Black     |     Total     |")?;
+        writeln!(f, "           |    MG     EG  |    MG     EG  |    MG     EG  |")?;
+        writeln!(f, "-----------+---------------+---------------+---------------+")?;
+        for term in TERMS {
+            let white = self.get(term, true);
+            let black = self.get(term, false);
+            write!(f, "{:>10} |", term)?;
+            write_score(f, white)?;
+            write_score(f, black)?;
+            write_score(f, white - black)?;
+            writeln!(f)?;
+        }
+        writeln!(f, "-----------+---------------+---------------+---------------+")?;
+        write!(f, "     Total |               |               |")?;
+        write_score(f, self.total())?;
+        writeln!(f)?;
+        writeln!(f)?;
+        writeln!(f, "Phase: {}/{}", self.phase, MAX_PHASE)?;
+        if let Some(name) = self.endgame {
+            writeln!(f, "Endgame: {}, terms alone give {:+.2}", name, self.tapered())?;
+        }
+        let score = self.score;
+        write!(f, "Final evaluation: {:+.2} (white side), {:+.2} (side to play)",
+            score, if self.white_to_play { score } else { -score })
+    }
+}
+
+/// Rank of `square` as seen from the side playing `is_white`.
+fn relative_rank(square: Square, is_white: bool) -> i8 {
+    if is_white { square.rank() } else { 7 - square.rank() }
+}
+
+fn is_light_square(square: Square) -> bool {
+    (square.rank() + square.file()) % 2 == 1
+}
+
+/// Whether an enemy pawn could ever attack `square`, i.e. one stands on an
+/// adjacent file further up the board from the point of view of `is_white`.
+fn can_be_attacked_by_pawn(square: Square, is_white: bool, enemy_pawns: Bitboard) -> bool {
+    enemy_pawns.into_iter().any(|pawn| {
+        (pawn.file() - square.file()).abs() == 1
+            && relative_rank(pawn, is_white) > relative_rank(square, is_white)
+    })
+}
+
+pub fn phase(pos: &Position) -> u32 {
+    let phase = pos.knights.count() * KNIGHT_PHASE
+        + pos.bishops.count() * BISHOP_PHASE
+        + pos.rooks.count() * ROOK_PHASE
+        + pos.queens.count() * QUEEN_PHASE;
+    phase.min(MAX_PHASE)
+}
+
+pub fn taper(score: Score, phase: u32) -> f32 {
+    (score.mg * phase as f32 + score.eg * (MAX_PHASE - phase) as f32) / MAX_PHASE as f32
+}
+
+/// Evaluates one side, recording every term and parameter use in `trace` if there is one.
+fn evaluate_side(pos: &Position, is_white: bool, params: &EvalParams, mut trace: Option<&mut Trace>) -> Score {
+    let mut total = Score::default();
+    let mut add = |term: Term, index: usize, count: i32| {
+        let score = params[index].times(count);
+        total += score;
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.add(term, is_white, score);
+            trace.coefficients[index] += if is_white { count } else { -count };
+        }
+    };
+
+    let us = pos.by_color(is_white);
+    let them = pos.by_color(!is_white);
+
+    add(Term::Material, QUEEN_VALUE, us.intersect(pos.queens).count() as i32);
+    add(Term::Material, ROOK_VALUE, us.intersect(pos.rooks).count() as i32);
+    add(Term::Material, BISHOP_VALUE, us.intersect(pos.bishops).count() as i32);
+    add(Term::Material, KNIGHT_VALUE, us.intersect(pos.knights).count() as i32);
+    add(Term::Material, PAWN_VALUE, us.intersect(pos.pawns).count() as i32);
+
+    let pawns = pos.pawns.intersect(us);
+    let enemy_pawns = pos.pawns.intersect(them);
+    for sq in pawns {
+        // Doubled pawns
+        if pawns.intersect(Bitboard::file(sq.file())).count() > 1 {
+            add(Term::Pawns, DOUBLED_PAWN, -1);
+        }
+
+        // Isolated pawn
+        if pawns.intersect(Bitboard::file(sq.file() + 1)).count() == 0
+            && pawns.intersect(Bitboard::file(sq.file() - 1)).count() == 0 {
+            add(Term::Pawns, ISOLATED_PAWN, -1);
+        }
+    }
+
+    // Squares we control with pawns, and squares that are unsafe for our pieces
+    let pawn_support = pawn_attacks(pawns, is_white);
+    let mobility_area = us.union(pawn_attacks(enemy_pawns, !is_white)).invert();
+    let mobility = |p_type: PieceType, sq: Square| {
+        attacks(pos, sq, Piece { is_white, p_type }).intersect(mobility_area).count() as usize
+    };
+
+    for sq in pos.knights.intersect(us) {
+        add(Term::Mobility, KNIGHT_MOBILITY + mobility(PieceType::Knight, sq), 1);
+
+        // Outpost
+        let rank = relative_rank(sq, is_white);
+        if (3..=5).contains(&rank) && pawn_support.get(sq) && !can_be_attacked_by_pawn(sq, is_white, enemy_pawns) {
+            add(Term::Knights, KNIGHT_OUTPOST, 1);
+        }
+    }
+
+    let bishops = pos.bishops.intersect(us);
+    if bishops.into_iter().any(is_light_square) && bishops.into_iter().any(|sq| !is_light_square(sq)) {
+        add(Term::Bishops, BISHOP_PAIR, 1);
+    }
+    for sq in bishops {
+        add(Term::Mobility, BISHOP_MOBILITY + mobility(PieceType::Bishop, sq), 1);
+
+        // Bad bishop, hemmed in by our own pawns on its color
+        let same_color_pawns = pawns.into_iter().filter(|&p| is_light_square(p) == is_light_square(sq)).count();
+        add(Term::Bishops, BAD_BISHOP_PAWN, -(same_color_pawns as i32));
+    }
+
+    let enemy_king_rank = pos.kings.intersect(them).into_iter().next().map(|k| relative_rank(k, is_white));
+    for sq in pos.rooks.intersect(us) {
+        add(Term::Mobility, ROOK_MOBILITY + mobility(PieceType::Rook, sq), 1);
+
+        // Open and semi-open files
+        let file = Bitboard::file(sq.file());
+        if pawns.intersect(file).count() == 0 {
+            if enemy_pawns.intersect(file).count() == 0 {
+                add(Term::Rooks, ROOK_OPEN_FILE, 1);
+            } else {
+                add(Term::Rooks, ROOK_SEMI_OPEN_FILE, 1);
+            }
+        }
+
+        // Rook on the seventh, trapping the king or attacking pawns
+        if relative_rank(sq, is_white) == 6 {
+            let seventh = Bitboard::rank(sq.rank());
+            if enemy_king_rank == Some(7) || enemy_pawns.intersect(seventh).count() > 0 {
+                add(Term::Rooks, ROOK_ON_SEVENTH, 1);
+            }
+        }
+    }
+
+    for sq in pos.queens.intersect(us) {
+        add(Term::Mobility, QUEEN_MOBILITY + mobility(PieceType::Queen, sq), 1);
+    }
+
+    total
+}
+
+/// Static evaluation of `pos` in pawns, from the point of view of the side to play.
+pub fn evaluate(pos: &Position, params: &EvalParams) -> f32 {
+    if pos.kings.intersect(pos.by_color(pos.white_to_play)).count() == 0 {
+        return f32::NEG_INFINITY;
+    }
+    if pos.kings.intersect(pos.by_color(!pos.white_to_play)).count() == 0 {
+        return f32::INFINITY;
+    }
+
+    let endgame = endgame::probe(pos);
+    if let Some(Endgame { strong_is_white, kind: EndgameKind::Value(f), .. }) = endgame {
+        let score = f(pos, strong_is_white);
+        return if pos.white_to_play == strong_is_white { score } else { -score };
+    }
+
+    let score = evaluate_side(pos, true, params, None) - evaluate_side(pos, false, params, None);
+    let mut score = taper(score, phase(pos));
+
+    if let Some(Endgame { strong_is_white, kind: EndgameKind::Scale(f), .. }) = endgame
+        && (score > 0f32) == strong_is_white {
+        score *= f(pos, strong_is_white);
+    }
+
+    if pos.white_to_play { score } else { -score }
+}
+
+/// Evaluates `pos` while recording the contribution of every term.
+pub fn trace(pos: &Position, params: &EvalParams) -> Trace {
+    let mut trace = Trace {
+        phase: phase(pos),
+        white_to_play: pos.white_to_play,
+        endgame: endgame::probe(pos).map(|e| e.name),
+        ..Trace::default()
+    };
+    evaluate_side(pos, true, params, Some(&mut trace));
+    evaluate_side(pos, false, params, Some(&mut trace));
+    let score = evaluate(pos, params);
+    trace.score = if pos.white_to_play { score } else { -score };
+    trace
+}
@@ -0,0 +1,143 @@
+use std::sync::OnceLock;
+
+use crate::square::Square;
+
+// King and pawn versus king bitbase, built by retrograde analysis in `init`
+// when the engine starts, or on the first probe otherwise. Positions are stored with the pawn belonging to white
+// and standing on files a-d; `probe` mirrors everything else into that shape.
+//
+// Squares here are plain indices `rank * 8 + file`.
+
+const MAX_INDEX: usize = 2 * 24 * 64 * 64;
+
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+fn index(white_to_play: bool, bksq: usize, wksq: usize, psq: usize) -> usize {
+    wksq | (bksq << 6) | ((!white_to_play as usize) << 12) | ((psq % 8) << 13) | ((6 - psq / 8) << 15)
+}
+
+fn distance(a: usize, b: usize) -> usize {
+    (a / 8).abs_diff(b / 8).max((a % 8).abs_diff(b % 8))
+}
+
+fn king_moves(sq: usize) -> impl Iterator<Item = usize> {
+    (0..64).filter(move |&s| distance(s, sq) == 1)
+}
+
+fn pawn_attacks(psq: usize, target: usize) -> bool {
+    target / 8 == psq / 8 + 1 && (target % 8).abs_diff(psq % 8) == 1
+}
+
+struct KpkPosition {
+    white_to_play: bool,
+    wksq: usize,
+    bksq: usize,
+    psq: usize,
+    result: u8
+}
+
+impl KpkPosition {
+    fn new(idx: usize) -> Self {
+        let wksq = idx & 63;
+        let bksq = (idx >> 6) & 63;
+        let white_to_play = (idx >> 12) & 1 == 0;
+        let psq = (6 - (idx >> 15)) * 8 + ((idx >> 13) & 3);
+
+        let result = if distance(wksq, bksq) <= 1 || wksq == psq || bksq == psq
+            || (white_to_play && pawn_attacks(psq, bksq)) {
+            INVALID
+        } else if white_to_play && psq / 8 == 6 && wksq != psq + 8
+            && (distance(bksq, psq + 8) > 1 || distance(wksq, psq + 8) == 1) {
+            // The pawn promotes safely
+            WIN
+        } else if !white_to_play
+            && (king_moves(bksq).all(|s| distance(s, wksq) <= 1 || pawn_attacks(psq, s))
+                || (distance(bksq, psq) == 1 && distance(wksq, psq) > 1)) {
+            // Stalemate, or the pawn is lost
+            DRAW
+        } else {
+            UNKNOWN
+        };
+
+        Self { white_to_play, wksq, bksq, psq, result }
+    }
+
+    /// Combines the results of every position reachable in one move. White
+    /// needs one winning move, black needs one drawing move.
+    fn classify(&self, db: &[KpkPosition]) -> u8 {
+        let (good, bad) = if self.white_to_play { (WIN, DRAW) } else { (DRAW, WIN) };
+        let mut r = INVALID;
+        if self.white_to_play {
+            for s in king_moves(self.wksq) {
+                r |= db[index(false, self.bksq, s, self.psq)].result;
+            }
+            if self.psq / 8 < 6 {
+                r |= db[index(false, self.bksq, self.wksq, self.psq + 8)].result;
+            }
+            if self.psq / 8 == 1 && self.psq + 8 != self.wksq && self.psq + 8 != self.bksq {
+                r |= db[index(false, self.bksq, self.wksq, self.psq + 16)].result;
+            }
+        } else {
+            for s in king_moves(self.bksq) {
+                r |= db[index(true, s, self.wksq, self.psq)].result;
+            }
+        }
+
+        if r & good != 0 {
+            good
+        } else if r & UNKNOWN != 0 {
+            UNKNOWN
+        } else {
+            bad
+        }
+    }
+}
+
+fn generate() -> Vec<u64> {
+    let mut db: Vec<KpkPosition> = (0..MAX_INDEX).map(KpkPosition::new).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for idx in 0..MAX_INDEX {
+            if db[idx].result == UNKNOWN {
+                let result = db[idx].classify(&db);
+                if result != UNKNOWN {
+                    db[idx].result = result;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let mut bits = vec![0u64; MAX_INDEX / 64];
+    for (idx, pos) in db.iter().enumerate() {
+        if pos.result == WIN {
+            bits[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+    bits
+}
+
+static BITBASE: OnceLock<Vec<u64>> = OnceLock::new();
+
+/// Builds the bitbase now, so the first search to reach KP vs K does not
+/// spend its time on it.
+pub fn init() {
+    BITBASE.get_or_init(generate);
+}
+
+/// Whether the side with the pawn wins. Squares are given from the point of
+/// view of the strong side, `strong_to_play` tells whose turn it is.
+pub fn probe(strong_king: Square, pawn: Square, weak_king: Square, strong_to_play: bool) -> bool {
+    // Mirror so the pawn is on files a-d
+    let flip = |sq: Square| {
+        let file = if pawn.file() >= 4 { 7 - sq.file() } else { sq.file() };
+        sq.rank() as usize * 8 + file as usize
+    };
+    let idx = index(strong_to_play, flip(weak_king), flip(strong_king), flip(pawn));
+    let bits = BITBASE.get_or_init(generate);
+    bits[idx / 64] & (1 << (idx % 64)) != 0
+}
@@ -0,0 +1,36 @@
+//! CrabGambit as a library: the board representation, move generation,
+//! evaluation and search behind the UCI engine, plus the tools built on them.
+
+// The original code returns explicitly and nests its conditions
+#![allow(clippy::needless_return, clippy::collapsible_if)]
+
+pub mod error;
+pub mod bitboard;
+pub mod square;
+pub mod piece;
+pub mod position;
+pub mod moves;
+mod transposition;
+pub mod eval;
+mod endgame;
+pub mod kpk;
+pub mod params;
+pub mod search;
+pub mod syzygy;
+pub mod polyglot;
+pub mod book;
+mod san;
+pub mod pgn;
+pub mod mate;
+pub mod tune;
+pub mod datagen;
+pub mod epdtest;
+pub mod bench;
+pub mod engine_match;
+pub mod play;
+pub mod uci;
+
+pub use error::{Error, PositionError};
+pub use moves::{generate_legal_moves, Move};
+pub use position::Position;
+pub use search::{search, SearchContext, SearchLimits, SearchResult};
@@ -0,0 +1,490 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::moves::{generate_legal_moves, is_attacked, Move, MoveList};
+use crate::position::Position;
+use crate::search::{search_and_report, MoveChain, SearchContext, SearchThread};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MateSolver {
+    /// Depth-first search over every defence, trying the attacker's checks first
+    AlphaBeta,
+    /// Best-first search expanding the most promising attacker line
+    ProofNumber
+}
+
+/// A proven mate for the side to move.
+pub struct Mate {
+    /// Number of attacker moves, including the mating one
+    pub moves: u32,
+    pub line: MoveChain,
+    pub nodes: u64
+}
+
+fn legal_moves(pos: &Position) -> Result<MoveList, Error> {
+    let mut moves = MoveList::new();
+    generate_legal_moves(&mut moves, &mut pos.snapshot())?;
+    Ok(moves)
+}
+
+fn in_check(pos: &Position) -> bool {
+    let kings = pos.kings & pos.by_color(pos.white_to_play);
+    kings.lsb().is_some_and(|square| is_attacked(pos, square, !pos.white_to_play))
+}
+
+/// Legal moves for the attacker, only the checking ones if `checks_only`.
+fn attacker_moves(pos: &Position, checks_only: bool) -> Result<Vec<(Move, Position)>, Error> {
+    let mut result = Vec::new();
+    for mv in legal_moves(pos)? {
+        let mut next = pos.snapshot();
+        next.do_move(mv)?;
+        if !checks_only || in_check(&next) {
+            result.push((mv, next));
+        }
+    }
+    Ok(result)
+}
+
+struct AlphaBetaSolver<'a> {
+    nodes: u64,
+    checks_only: bool,
+    stop: &'a AtomicBool
+}
+
+impl AlphaBetaSolver<'_> {
+    /// Finds a move that mates within `moves` attacker moves, with its line.
+    /// Once stopped it finds nothing, so no defence is refuted by mistake.
+    fn attack(&mut self, pos: &Position, moves: u32) -> Result<Option<MoveChain>, Error> {
+        self.nodes += 1;
+        if self.stop.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        // The mating move is always a check, earlier ones only in checks-only mode
+        for (mv, next) in attacker_moves(pos, self.checks_only || moves == 1)? {
+            if let Some(line) = self.defend(&next, moves)? {
+                return Ok(Some(MoveChain::new(mv, line)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether every defence loses within `moves` attacker moves, counting the
+    /// one just played, with the line of the longest defence. `Some(None)`
+    /// means the defender is already mated.
+    fn defend(&mut self, pos: &Position, moves: u32) -> Result<Option<Option<MoveChain>>, Error> {
+        self.nodes += 1;
+        if self.stop.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let defences = legal_moves(pos)?;
+        if defences.is_empty() {
+            return Ok(if in_check(pos) { Some(None) } else { None });
+        }
+        if moves == 1 {
+            return Ok(None);
+        }
+        // Keep the defence holding out longest, with the quickest mate against it
+        let mut longest: Option<(u32, MoveChain)> = None;
+        for mv in defences {
+            let mut next = pos.snapshot();
+            next.do_move(mv)?;
+            // A defence mated no later than the longest one so far changes nothing
+            let known = longest.as_ref().map_or(0, |(length, _)| *length);
+            if known > 0 && self.attack(&next, known)?.is_some() {
+                continue;
+            }
+            let mut mate = None;
+            for length in known + 1..moves {
+                if let Some(line) = self.attack(&next, length)? {
+                    mate = Some((length, line));
+                    break;
+                }
+            }
+            let Some((length, line)) = mate else {
+                return Ok(None);
+            };
+            longest = Some((length, MoveChain::new(mv, Some(line))));
+        }
+        Ok(Some(longest.map(|(_, line)| line)))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PnValue {
+    Proven,
+    Disproven,
+    Unknown
+}
+
+/// A node of the proof-number tree. Positions are not stored but replayed
+/// from the root, and children are linked through their siblings so the
+/// quiet moves can be added to a checks-only node later.
+struct PnNode {
+    /// Move leading here, `None` at the root
+    mv: Option<Move>,
+    parent: u32,
+    /// 0 for none, as the root is no node's child or sibling
+    first_child: u32,
+    next_sibling: u32,
+    proof: u32,
+    disproof: u32,
+    /// Plies from the root, the attacker moving at even ones
+    ply: u16,
+    expanded: bool,
+    /// Only the attacker's checks were added on expanding
+    checks_only: bool,
+    /// Mate or stalemate, whose value never changes
+    terminal: bool
+}
+
+impl PnNode {
+    fn is_or(&self) -> bool {
+        self.ply.is_multiple_of(2)
+    }
+
+    fn set_value(&mut self, value: PnValue) {
+        (self.proof, self.disproof) = match value {
+            PnValue::Proven => (0, u32::MAX),
+            PnValue::Disproven => (u32::MAX, 0),
+            PnValue::Unknown => (1, 1)
+        };
+    }
+}
+
+/// Memory the proof-number tree may take up before the search gives up.
+const PN_MEMORY: usize = 256 << 20;
+const MAX_PN_NODES: usize = PN_MEMORY / size_of::<PnNode>();
+
+/// One tree kept over every length and pass of `search_mate`: a longer mate
+/// reopens the nodes cut off at the old length, and the full pass adds the
+/// quiet moves to the nodes expanded with checks only.
+struct ProofNumberSolver<'a> {
+    root: Position,
+    nodes: Vec<PnNode>,
+    /// Attacker moves allowed from the root
+    max_moves: u32,
+    checks_only: bool,
+    stop: &'a AtomicBool
+}
+
+impl<'a> ProofNumberSolver<'a> {
+    fn new(pos: &Position, stop: &'a AtomicBool) -> Self {
+        Self {
+            root: pos.snapshot(),
+            nodes: vec![PnNode {
+                mv: None,
+                parent: 0,
+                first_child: 0,
+                next_sibling: 0,
+                proof: 1,
+                disproof: 1,
+                ply: 0,
+                expanded: false,
+                checks_only: false,
+                terminal: false
+            }],
+            max_moves: 0,
+            checks_only: true,
+            stop
+        }
+    }
+
+    /// Attacker moves still allowed from the node, counting the one about to
+    /// be played at defender nodes.
+    fn moves_left(&self, ply: u16) -> u32 {
+        self.max_moves - ply as u32 / 2
+    }
+
+    fn children(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        let next = |c: u32| (c != 0).then_some(c as usize);
+        std::iter::successors(next(self.nodes[index].first_child), move |&c| next(self.nodes[c].next_sibling))
+    }
+
+    fn position(&self, mut index: usize) -> Result<Position, Error> {
+        let mut moves = Vec::new();
+        while let Some(mv) = self.nodes[index].mv {
+            moves.push(mv);
+            index = self.nodes[index].parent as usize;
+        }
+        let mut pos = self.root.snapshot();
+        for mv in moves.into_iter().rev() {
+            pos.do_move(mv)?;
+        }
+        Ok(pos)
+    }
+
+    /// Adds the children of the node at `pos`: every defence, or the
+    /// attacker's moves, limited to checks where they are all that can mate
+    /// or to the quiet moves when completing a checks-only node.
+    fn expand(&mut self, index: usize, pos: &mut Position, quiet: bool) -> Result<(), Error> {
+        let node = &self.nodes[index];
+        let (is_or, ply) = (node.is_or(), node.ply + 1);
+        let checks_only = is_or && !quiet && (self.checks_only || self.moves_left(node.ply) == 1);
+        let first = self.nodes.len();
+        for mv in legal_moves(pos)? {
+            pos.do_move(mv)?;
+            let check = in_check(pos);
+            if !is_or || check && !quiet || !check && !checks_only {
+                // The defender replies, mated if it has no moves while in check
+                let terminal = is_or && legal_moves(pos)?.is_empty();
+                let mut child = PnNode {
+                    mv: Some(mv),
+                    parent: index as u32,
+                    first_child: 0,
+                    next_sibling: self.nodes.len() as u32 + 1,
+                    proof: 1,
+                    disproof: 1,
+                    ply,
+                    expanded: false,
+                    checks_only: false,
+                    terminal
+                };
+                child.set_value(match terminal {
+                    true if check => PnValue::Proven,
+                    true => PnValue::Disproven,
+                    false => self.leaf_value(ply)
+                });
+                self.nodes.push(child);
+            }
+            pos.undo_move()?;
+        }
+        // The new children go in front of any earlier ones
+        let last = self.nodes.len() - 1;
+        if last >= first {
+            self.nodes[last].next_sibling = self.nodes[index].first_child;
+            self.nodes[index].first_child = first as u32;
+        }
+        let node = &mut self.nodes[index];
+        node.expanded = true;
+        node.checks_only = checks_only;
+        Ok(())
+    }
+
+    /// Value of a leaf, disproven once the attacker has no move left to mate with.
+    fn leaf_value(&self, ply: u16) -> PnValue {
+        if ply % 2 == 1 && self.moves_left(ply) == 1 { PnValue::Disproven } else { PnValue::Unknown }
+    }
+
+    fn update(&mut self, index: usize) {
+        let node = &self.nodes[index];
+        if node.terminal {
+            return;
+        }
+        if !node.expanded {
+            let value = self.leaf_value(node.ply);
+            self.nodes[index].set_value(value);
+            return;
+        }
+        if node.first_child == 0 {
+            // No checks to give, or no moves at all
+            self.nodes[index].set_value(PnValue::Disproven);
+            return;
+        }
+        let proofs = self.children(index).map(|c| self.nodes[c].proof);
+        let disproofs = self.children(index).map(|c| self.nodes[c].disproof);
+        let (proof, disproof) = if node.is_or() {
+            (proofs.min().unwrap(), disproofs.fold(0u32, |a, b| a.saturating_add(b)))
+        } else {
+            (proofs.fold(0u32, |a, b| a.saturating_add(b)), disproofs.min().unwrap())
+        };
+        self.nodes[index].proof = proof;
+        self.nodes[index].disproof = disproof;
+    }
+
+    /// Looks for a mate within `max_moves`, keeping the tree of earlier calls.
+    fn solve(&mut self, max_moves: u32, checks_only: bool) -> Result<Option<MoveChain>, Error> {
+        self.max_moves = max_moves;
+        self.checks_only = checks_only;
+        if !checks_only {
+            let partial: Vec<usize> = (0..self.nodes.len()).filter(|&i| {
+                let node = &self.nodes[i];
+                node.checks_only && self.moves_left(node.ply) > 1
+            }).collect();
+            for index in partial {
+                let mut pos = self.position(index)?;
+                self.expand(index, &mut pos, true)?;
+            }
+        }
+        // Children come after their parents, so one backward pass updates the whole tree
+        for index in (0..self.nodes.len()).rev() {
+            self.update(index);
+        }
+
+        while self.nodes[0].proof != 0 && self.nodes[0].disproof != 0 {
+            if self.nodes.len() >= MAX_PN_NODES || self.stop.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            // Walk down to the most proving node
+            let mut pos = self.root.snapshot();
+            let mut index = 0;
+            while self.nodes[index].expanded {
+                let is_or = self.nodes[index].is_or();
+                index = self.children(index).min_by_key(|&c| {
+                    if is_or { self.nodes[c].proof } else { self.nodes[c].disproof }
+                }).unwrap();
+                pos.do_move(self.nodes[index].mv.unwrap())?;
+            }
+            self.expand(index, &mut pos, false)?;
+            loop {
+                self.update(index);
+                if index == 0 {
+                    break;
+                }
+                index = self.nodes[index].parent as usize;
+            }
+        }
+        Ok(if self.nodes[0].proof == 0 { self.line(0) } else { None })
+    }
+
+    /// Plies to mate from a proven node, the attacker taking its quickest
+    /// mate and the defender its longest defence.
+    fn mate_plies(&self, index: usize) -> u32 {
+        let lengths = self.children(index)
+            .filter(|&c| self.nodes[c].proof == 0)
+            .map(|c| self.mate_plies(c) + 1);
+        if self.nodes[index].is_or() { lengths.min() } else { lengths.max() }.unwrap_or(0)
+    }
+
+    /// The proven line: the quickest mate against the longest defence.
+    fn line(&self, index: usize) -> Option<MoveChain> {
+        let proven = self.children(index).filter(|&c| self.nodes[c].proof == 0);
+        let child = if self.nodes[index].is_or() {
+            proven.min_by_key(|&c| self.mate_plies(c))
+        } else {
+            proven.min_by_key(|&c| std::cmp::Reverse(self.mate_plies(c)))
+        }?;
+        Some(MoveChain::new(self.nodes[child].mv?, self.line(child)))
+    }
+}
+
+/// Looks for the shortest mate in at most `max_moves` moves for the side to
+/// move. Each length is first tried with checking moves only, which finds
+/// most mates quickly, then with every attacker move. Setting `stop` ends the
+/// search without a mate.
+pub fn search_mate(pos: &Position, max_moves: u32, solver: MateSolver, stop: &AtomicBool) -> Result<Option<Mate>, Error> {
+    let mut nodes = 0;
+    let mut tree = ProofNumberSolver::new(pos, stop);
+    for moves in 1..=max_moves {
+        for checks_only in [true, false] {
+            let line = match solver {
+                MateSolver::AlphaBeta => {
+                    let mut search = AlphaBetaSolver { nodes: 0, checks_only, stop };
+                    let line = search.attack(pos, moves)?;
+                    nodes += search.nodes;
+                    line
+                },
+                MateSolver::ProofNumber => {
+                    let line = tree.solve(moves, checks_only)?;
+                    nodes = tree.nodes.len() as u64;
+                    line
+                }
+            };
+            if let Some(line) = line {
+                return Ok(Some(Mate { moves, line, nodes }));
+            }
+            if stop.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            // With a single move both passes are the same
+            if moves == 1 {
+                break;
+            }
+        }
+    }
+    Ok(None)
+}
+
+
+/// Starts `search_mate` on a new thread like `iterative_deepening`, printing
+/// the mate and its first move once proven. Without a mate, or when stopped
+/// before finding one, it plays `book_move` or falls back to a normal search
+/// of up to `max_time`.
+pub fn mate_thread(ctx: Arc<Mutex<SearchContext>>, mut pos: Position, max_moves: u32, solver: MateSolver, book_move: Option<Move>, max_time: Duration) -> SearchThread {
+    SearchThread::spawn(move |stop| {
+        let start_time = Instant::now();
+        match search_mate(&pos, max_moves, solver, stop) {
+            Ok(Some(mate)) => {
+                println!("info depth {} time {} nodes {} score mate {} pv {}",
+                    2 * mate.moves - 1,
+                    start_time.elapsed().as_millis(),
+                    mate.nodes,
+                    mate.moves,
+                    mate.line
+                );
+                println!("bestmove {}", mate.line.current);
+                return;
+            },
+            Ok(None) if !stop.load(Ordering::Relaxed) => println!("info string No mate in {} found", max_moves),
+            Ok(None) => {},
+            Err(e) => println!("info string {}", e)
+        }
+        match book_move {
+            Some(mv) => println!("bestmove {}", mv),
+            None => search_and_report(&ctx, &mut pos, stop, max_time)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOLVERS: [MateSolver; 2] = [MateSolver::AlphaBeta, MateSolver::ProofNumber];
+
+    fn solve(fen: &str, max_moves: u32, solver: MateSolver) -> Option<Mate> {
+        let pos = Position::from_fen(fen).unwrap();
+        search_mate(&pos, max_moves, solver, &AtomicBool::new(false)).unwrap()
+    }
+
+    #[test]
+    fn mate_in_three() {
+        let fen = "r1b1kb1r/pppp1ppp/5q2/4n3/3KP3/2N3PN/PPP4P/R1BQ1B1R b kq - 0 1";
+        for solver in SOLVERS {
+            let mate = solve(fen, 3, solver).unwrap();
+            assert_eq!(mate.moves, 3);
+            // The king walks into the longest defence
+            assert_eq!(mate.line.to_string(), "f8c5 d4c5 f6b6 c5d5 b6d6");
+        }
+        assert!(solve(fen, 2, MateSolver::ProofNumber).is_none());
+    }
+
+    #[test]
+    fn shortest_mate() {
+        // Longer mates abound, the back rank one is the quickest
+        for solver in SOLVERS {
+            let mate = solve("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3, solver).unwrap();
+            assert_eq!((mate.moves, mate.line.to_string()), (1, "a1a8".to_string()));
+        }
+    }
+
+    #[test]
+    fn stalemate_is_no_mate() {
+        // The light-squared bishop can only take away h7, stalemating
+        for solver in SOLVERS {
+            assert!(solve("7k/5K2/8/8/8/8/8/1B6 w - - 0 1", 2, solver).is_none());
+        }
+    }
+
+    #[test]
+    fn quiet_first_move() {
+        // No check mates in two, so the king has to step up first
+        let fen = "7k/8/5K2/8/8/8/8/R7 w - - 0 1";
+        for solver in SOLVERS {
+            let mate = solve(fen, 2, solver).unwrap();
+            assert_eq!(mate.moves, 2);
+            let mut pos = Position::from_fen(fen).unwrap();
+            pos.do_move(mate.line.current).unwrap();
+            assert!(!in_check(&pos));
+        }
+    }
+
+    #[test]
+    fn stopped() {
+        let pos = Position::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        for solver in SOLVERS {
+            assert!(search_mate(&pos, 3, solver, &AtomicBool::new(true)).unwrap().is_none());
+        }
+    }
+}
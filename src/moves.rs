@@ -1,63 +1,218 @@
 use std::fmt::Display;
+use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
+use crate::bitboard::Bitboard;
+use crate::error::Error;
 use crate::position::Position;
 use crate::square::Square;
 use crate::piece::{Piece, PieceType};
 
+/// Flags in the top four bits of a `Move`. Promotions set the promotion bit
+/// and keep the piece in the low two bits, captures set the capture bit.
+const QUIET: u16 = 0;
+const DOUBLE_PUSH: u16 = 1;
+const CASTLE: u16 = 2;
+const CAPTURE: u16 = 4;
+const EN_PASSANT: u16 = CAPTURE | 1;
+const PROMOTION: u16 = 8;
+
+const PROMOTION_TYPES: [PieceType; 4] = [PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen];
+
+/// A move packed into 16 bits: the source square, the destination square and
+/// four bits of flags. Castling is written as the king moving two squares, or
+/// as the king taking its own rook in Chess960.
 #[derive(Clone, Copy, PartialEq)]
-pub struct Move {
-    pub src: Square,
-    pub dest: Square,
-    pub promotion: Option<PieceType>
+pub struct Move(u16);
+
+impl Move {
+    const fn new(src: Square, dest: Square, flags: u16) -> Move {
+        Move(src.index() as u16 | (dest.index() as u16) << 6 | flags << 12)
+    }
+
+    pub const fn quiet(src: Square, dest: Square) -> Move {
+        Move::new(src, dest, QUIET)
+    }
+
+    pub const fn capture(src: Square, dest: Square) -> Move {
+        Move::new(src, dest, CAPTURE)
+    }
+
+    pub const fn double_push(src: Square, dest: Square) -> Move {
+        Move::new(src, dest, DOUBLE_PUSH)
+    }
+
+    pub const fn en_passant(src: Square, dest: Square) -> Move {
+        Move::new(src, dest, EN_PASSANT)
+    }
+
+    pub const fn castle(src: Square, dest: Square) -> Move {
+        Move::new(src, dest, CASTLE)
+    }
+
+    pub fn promotion_to(src: Square, dest: Square, p_type: PieceType, capture: bool) -> Move {
+        let piece = PROMOTION_TYPES.iter().position(|&t| t == p_type).unwrap_or(3) as u16;
+        Move::new(src, dest, PROMOTION | if capture { CAPTURE } else { 0 } | piece)
+    }
+
+    fn flags(self) -> u16 {
+        self.0 >> 12
+    }
+
+    pub fn src(self) -> Square {
+        Square::from_index((self.0 & 63) as usize)
+    }
+
+    pub fn dest(self) -> Square {
+        Square::from_index((self.0 >> 6 & 63) as usize)
+    }
+
+    pub fn promotion(self) -> Option<PieceType> {
+        (self.flags() & PROMOTION != 0).then(|| PROMOTION_TYPES[(self.flags() & 3) as usize])
+    }
+
+    /// Whether the move takes a piece, en passant included.
+    pub fn is_capture(self) -> bool {
+        self.flags() & CAPTURE != 0
+    }
+
+    pub fn is_en_passant(self) -> bool {
+        self.flags() == EN_PASSANT
+    }
+
+    pub fn is_castle(self) -> bool {
+        self.flags() == CASTLE
+    }
+
+    pub fn is_double_push(self) -> bool {
+        self.flags() == DOUBLE_PUSH
+    }
+
+    /// Whether both moves have the same squares and promotion, ignoring the
+    /// flags, which a move read from text does not have.
+    pub fn same_squares(self, other: Move) -> bool {
+        self.0 & 0x0fff == other.0 & 0x0fff && self.promotion() == other.promotion()
+    }
 }
 
 impl Display for Move {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}", self.src, self.dest)?;
-        if let Some(promotion_type) = self.promotion {
+        write!(f, "{}{}", self.src(), self.dest())?;
+        if let Some(promotion_type) = self.promotion() {
             write!(f, "{}", Piece::black(promotion_type))?;
         }
         Ok(())
     }
 }
 
+/// Reads a move in UCI notation. Only the squares and promotion are known
+/// from the text, `Position::parse_legal_move` also fills in the flags.
 impl FromStr for Move {
-    type Err = String;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Move {
-            src: s[0..2].parse()?,
-            dest: s[2..4].parse()?,
-            promotion: match s.chars().nth(4) {
-                Some('q') => Some(PieceType::Queen),
-                Some('r') => Some(PieceType::Rook),
-                Some('b') => Some(PieceType::Bishop),
-                Some('n') => Some(PieceType::Knight),
-                _ => None
+        let invalid = || Error::Parse(format!("Invalid move '{}'", s));
+        let (Some(src), Some(dest)) = (s.get(0..2), s.get(2..4)) else {
+            return Err(invalid());
+        };
+        let src = src.parse().map_err(|_| invalid())?;
+        let dest = dest.parse().map_err(|_| invalid())?;
+        let promotion = match &s[4..] {
+            "" => return Ok(Move::quiet(src, dest)),
+            "q" => PieceType::Queen,
+            "r" => PieceType::Rook,
+            "b" => PieceType::Bishop,
+            "n" => PieceType::Knight,
+            _ => return Err(invalid())
+        };
+        Ok(Move::promotion_to(src, dest, promotion, false))
+    }
+}
+
+/// Most moves any position can have, with room to spare.
+pub const MAX_MOVES: usize = 256;
+
+/// A list of moves kept on the stack.
+#[derive(Clone)]
+pub struct MoveList {
+    moves: [Move; MAX_MOVES],
+    len: usize
+}
+
+impl MoveList {
+    pub fn new() -> MoveList {
+        MoveList {
+            moves: [Move(0); MAX_MOVES],
+            len: 0
+        }
+    }
+
+    pub fn push(&mut self, mv: Move) {
+        self.moves[self.len] = mv;
+        self.len += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&Move) -> bool) {
+        let mut len = 0;
+        for i in 0..self.len {
+            if keep(&self.moves[i]) {
+                self.moves[len] = self.moves[i];
+                len += 1;
             }
-        })
+        }
+        self.len = len;
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
-pub struct PastMove {
-    pub mv: Move,
-    pub captured_peice: Option<Piece>,
-    pub en_passant_target: Option<Square>
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-fn generate_move(moves: &mut Vec<Move>, pos: &Position, src: Square, dest: Square, capture: bool) -> bool {
-    if !dest.is_valid() {
-        return false;
+impl Deref for MoveList {
+    type Target = [Move];
+
+    fn deref(&self) -> &[Move] {
+        &self.moves[..self.len]
+    }
+}
+
+impl DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [Move] {
+        &mut self.moves[..self.len]
+    }
+}
+
+impl IntoIterator for MoveList {
+    type Item = Move;
+    type IntoIter = std::iter::Take<std::array::IntoIter<Move, MAX_MOVES>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.moves.into_iter().take(self.len)
     }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+    type IntoIter = std::slice::Iter<'a, Move>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+fn generate_move(moves: &mut MoveList, pos: &Position, src: Square, dest: Option<Square>, capture: bool) -> bool {
+    let Some(dest) = dest else {
+        return false;
+    };
     if capture {
         let is_white = pos.white_pieces.get(src);
         if pos.by_color(!is_white).get(dest) {
-            moves.push(Move {
-                src,
-                dest,
-                promotion: None
-            });
+            moves.push(Move::capture(src, dest));
             return false;
         } else if pos.by_color(is_white).get(dest) {
             return false;
@@ -66,75 +221,77 @@ fn generate_move(moves: &mut Vec<Move>, pos: &Position, src: Square, dest: Squar
         if pos.all_pieces().get(dest) {
             return false;
         } else {
-            moves.push(Move {
-                src,
-                dest,
-                promotion: None
-            });
+            moves.push(Move::quiet(src, dest));
         }
     }
     return true;
 }
 
-fn generate_direction_moves(moves: &mut Vec<Move>, pos: &Position, src: Square, dr: i8, df: i8, capture: bool) {
-    let mut dest = src.clone();
-    loop {
-        dest.rank += dr;
-        dest.file += df;
-        if !generate_move(moves, pos, src, dest, capture) {
-            return;
-        }
+fn generate_direction_moves(moves: &mut MoveList, pos: &Position, src: Square, dr: i8, df: i8, capture: bool) {
+    let mut dest = src.offset(dr, df);
+    while generate_move(moves, pos, src, dest, capture) {
+        dest = dest.and_then(|dest| dest.offset(dr, df));
     }
 }
 
-fn generate_pawn_moves(moves: &mut Vec<Move>, pos: &Position, src: Square, capture: bool) {
-    let direction = if pos.white_pieces.get(src) { 1 } else { -1 };
+fn push_promotions(moves: &mut MoveList, src: Square, dest: Square, capture: bool) {
+    for p_type in [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight] {
+        moves.push(Move::promotion_to(src, dest, p_type, capture));
+    }
+}
+
+fn generate_pawn_moves(moves: &mut MoveList, pos: &Position, src: Square, capture: bool) {
+    let is_white = pos.white_pieces.get(src);
+    let direction = if is_white { 1 } else { -1 };
     if capture {
-        generate_move(moves, pos, src, src.add(direction, 1), true);
-        generate_move(moves, pos, src, src.add(direction, -1), true);
+        for df in [1, -1] {
+            let Some(dest) = src.offset(direction, df) else {
+                continue;
+            };
+            if dest.rank() == 7 || dest.rank() == 0 {
+                if pos.by_color(!is_white).get(dest) {
+                    push_promotions(moves, src, dest, true);
+                }
+            } else {
+                generate_move(moves, pos, src, Some(dest), true);
+            }
+        }
         if let Some(target) = pos.en_passant_target {
-            if target.rank == src.rank && (target.file - src.file).abs() == 1 {
-                moves.push(Move {
-                    src,
-                    dest: target.add(direction, 0),
-                    promotion: None
-                });
+            if target.rank() == src.rank() && (target.file() - src.file()).abs() == 1 {
+                moves.push(Move::en_passant(src, Square::new(target.rank() + direction, target.file())));
             }
         }
     } else {
-        let dest = src.add(direction, 0);
-        if dest.rank == 7 || dest.rank == 0 {
+        // Pawns never stand on the back ranks, so there is always a square ahead
+        let dest = Square::new(src.rank() + direction, src.file());
+        if dest.rank() == 7 || dest.rank() == 0 {
             if !pos.all_pieces().get(dest) {
-                moves.push(Move { src, dest, promotion: Some(PieceType::Queen) });
-                moves.push(Move { src, dest, promotion: Some(PieceType::Rook) });
-                moves.push(Move { src, dest, promotion: Some(PieceType::Bishop) });
-                moves.push(Move { src, dest, promotion: Some(PieceType::Knight) });
+                push_promotions(moves, src, dest, false);
             }
-        } else {
-            if generate_move(moves, pos, src, dest, false) {
-                if src.rank == 1 || src.rank == 6 {
-                    generate_move(moves, pos, src, dest.add(direction, 0), false);
-                }
+        } else if generate_move(moves, pos, src, Some(dest), false) && (src.rank() == 1 || src.rank() == 6) {
+            let double = Square::new(dest.rank() + direction, dest.file());
+            if !pos.all_pieces().get(double) {
+                moves.push(Move::double_push(src, double));
             }
         }
     }
 }
 
-fn generate_knight_moves(moves: &mut Vec<Move>, pos: &Position, src: Square, capture: bool) {
-    generate_move(moves, pos, src, src.add(2, 1), capture);
-    generate_move(moves, pos, src, src.add(2, -1), capture);
+fn generate_knight_moves(moves: &mut MoveList, pos: &Position, src: Square, capture: bool) {
+    generate_move(moves, pos, src, src.offset(2, 1), capture);
+    generate_move(moves, pos, src, src.offset(2, -1), capture);
 
-    generate_move(moves, pos, src, src.add(-2, 1), capture);
-    generate_move(moves, pos, src, src.add(-2, -1), capture);
+    generate_move(moves, pos, src, src.offset(-2, 1), capture);
+    generate_move(moves, pos, src, src.offset(-2, -1), capture);
 
-    generate_move(moves, pos, src, src.add(1, 2), capture);
-    generate_move(moves, pos, src, src.add(-1, 2), capture);
+    generate_move(moves, pos, src, src.offset(1, 2), capture);
+    generate_move(moves, pos, src, src.offset(-1, 2), capture);
 
-    generate_move(moves, pos, src, src.add(1, -2), capture);
-    generate_move(moves, pos, src, src.add(-1, -2), capture);
+    generate_move(moves, pos, src, src.offset(1, -2), capture);
+    generate_move(moves, pos, src, src.offset(-1, -2), capture);
 }
 
-fn generate_rook_moves(moves: &mut Vec<Move>, pos: &Position, src: Square, capture: bool) {
+fn generate_rook_moves(moves: &mut MoveList, pos: &Position, src: Square, capture: bool) {
     generate_direction_moves(moves, pos, src, 0, 1, capture);
     generate_direction_moves(moves, pos, src, 0, -1, capture);
 
@@ -142,7 +299,7 @@ fn generate_rook_moves(moves: &mut Vec<Move>, pos: &Position, src: Square, captu
     generate_direction_moves(moves, pos, src, -1, 0, capture);
 }
 
-fn generate_bishop_moves(moves: &mut Vec<Move>, pos: &Position, src: Square, capture: bool) {
+fn generate_bishop_moves(moves: &mut MoveList, pos: &Position, src: Square, capture: bool) {
     generate_direction_moves(moves, pos, src, 1, 1, capture);
     generate_direction_moves(moves, pos, src, -1, -1, capture);
 
@@ -150,71 +307,76 @@ fn generate_bishop_moves(moves: &mut Vec<Move>, pos: &Position, src: Square, cap
     generate_direction_moves(moves, pos, src, 1, -1, capture);
 }
 
-fn generate_queen_moves(moves: &mut Vec<Move>, pos: &Position, src: Square, capture: bool) {
+fn generate_queen_moves(moves: &mut MoveList, pos: &Position, src: Square, capture: bool) {
     generate_rook_moves(moves, pos, src, capture);
     generate_bishop_moves(moves, pos, src, capture);
 }
 
-fn generate_castle(moves: &mut Vec<Move>, pos: &Position, src: Square, dest: Square) {
-    let castle_state = if pos.white_pieces.get(src) {
-        &pos.white_castle_state
-    } else {
-        &pos.black_castle_state
-    };
+fn generate_castle(moves: &mut MoveList, pos: &Position, src: Square, short: bool) {
+    let is_white = pos.white_pieces.get(src);
+    let castle_state = pos.castle_state(is_white);
+    if !(if short { castle_state.can_short_castle } else { castle_state.can_long_castle }) {
+        return;
+    }
+
+    // The rook must still be there
+    let rank = src.rank();
+    let rook_square = Square::new(rank, castle_state.rook_file(short));
+    if !pos.rooks.intersect(pos.by_color(is_white)).get(rook_square) {
+        return;
+    }
 
+    // Everything between where the king and rook start and end must be empty
+    let king_dest = Square::new(rank, if short { 6 } else { 2 });
+    let rook_dest = Square::new(rank, if short { 5 } else { 3 });
+    let files = [src.file(), king_dest.file(), rook_square.file(), rook_dest.file()];
     let pieces = pos.all_pieces();
-    if dest.file > src.file {
-        if !castle_state.can_short_castle {
-            return;
-        }
-        if pieces.get(src.add(0, 1)) {
-            return;
-        }
-        if pieces.get(src.add(0, 2)) {
-            return;
-        }
-    } else {
-        if !castle_state.can_long_castle {
+    for file in *files.iter().min().unwrap()..=*files.iter().max().unwrap() {
+        if file != src.file() && file != rook_square.file() && pieces.get(Square::new(rank, file)) {
             return;
         }
-        if pieces.get(src.add(0, -1)) {
-            return;
-        }
-        if pieces.get(src.add(0, -2)) {
+    }
+
+    // The king may not castle out of, through or into check. The rook is
+    // lifted first, as in Chess960 it can block an attack on the king's path.
+    let mut without_rook = pos.snapshot();
+    without_rook.remove_piece(rook_square);
+    let step = if king_dest.file() > src.file() { 1 } else { -1 };
+    let mut square = src;
+    loop {
+        if is_attacked(&without_rook, square, !is_white) {
             return;
         }
-        if pieces.get(src.add(0, -3)) {
-            return;
+        if square == king_dest {
+            break;
         }
+        square = Square::new(rank, square.file() + step);
     }
 
-    moves.push(Move {
-        src,
-        dest,
-        promotion: None
-    });
+    moves.push(Move::castle(src, if pos.chess960 { rook_square } else { king_dest }));
 }
 
-fn generate_king_moves(moves: &mut Vec<Move>, pos: &Position, src: Square, capture: bool) {
-    generate_move(moves, pos, src, src.add(0, 1), capture);
-    generate_move(moves, pos, src, src.add(0, -1), capture);
+fn generate_king_moves(moves: &mut MoveList, pos: &Position, src: Square, capture: bool) {
+    generate_move(moves, pos, src, src.offset(0, 1), capture);
+    generate_move(moves, pos, src, src.offset(0, -1), capture);
 
-    generate_move(moves, pos, src, src.add(1, 0), capture);
-    generate_move(moves, pos, src, src.add(-1, 0), capture);
+    generate_move(moves, pos, src, src.offset(1, 0), capture);
+    generate_move(moves, pos, src, src.offset(-1, 0), capture);
 
-    generate_move(moves, pos, src, src.add(1, 1), capture);
-    generate_move(moves, pos, src, src.add(-1, -1), capture);
+    generate_move(moves, pos, src, src.offset(1, 1), capture);
+    generate_move(moves, pos, src, src.offset(-1, -1), capture);
 
-    generate_move(moves, pos, src, src.add(1, -1), capture);
-    generate_move(moves, pos, src, src.add(-1, 1), capture);
+    generate_move(moves, pos, src, src.offset(1, -1), capture);
+    generate_move(moves, pos, src, src.offset(-1, 1), capture);
 
-    if !capture && src.rank == 0 && src.file == 4 {
-        generate_castle(moves, pos, src, src.add(0, 2));
-        generate_castle(moves, pos, src, src.add(0, -2));
+    let home_rank = if pos.white_pieces.get(src) { 0 } else { 7 };
+    if !capture && src.rank() == home_rank {
+        generate_castle(moves, pos, src, true);
+        generate_castle(moves, pos, src, false);
     }
 }
 
-pub fn generate_moves(moves: &mut Vec<Move>, pos: &Position, capture: bool) {
+pub fn generate_moves(moves: &mut MoveList, pos: &Position, capture: bool) {
     let to_play = pos.by_color(pos.white_to_play);
 
     if pos.kings.count() < 2 {
@@ -241,18 +403,90 @@ pub fn generate_moves(moves: &mut Vec<Move>, pos: &Position, capture: bool) {
     }
 }
 
-pub fn generate_legal_moves(moves: &mut Vec<Move>, pos: &mut Position) -> Result<(), String> {
-    let mut pseudo_legal: Vec<Move> = Vec::new();
+pub fn generate_legal_moves(moves: &mut MoveList, pos: &mut Position) -> Result<(), Error> {
+    let mut pseudo_legal = MoveList::new();
     generate_moves(&mut pseudo_legal, pos, true);
     generate_moves(&mut pseudo_legal, pos, false);
     for mv in pseudo_legal {
-        let past_move = pos.do_move(mv)?;
-        pos.do_null_move();
-        if !pos.is_check()? {
+        pos.do_move(mv)?;
+        // The side that just moved may not leave its king attacked
+        let king = pos.kings.intersect(pos.by_color(!pos.white_to_play)).lsb();
+        if king.is_some_and(|king| !is_attacked(pos, king, pos.white_to_play)) {
             moves.push(mv);
         }
-        pos.undo_null_move();
-        pos.undo_move(past_move)?;
+        pos.undo_move()?;
     }
     Ok(())
 }
+
+const KNIGHT_OFFSETS: [(i8, i8); 8] = [(2, 1), (2, -1), (-2, 1), (-2, -1), (1, 2), (-1, 2), (1, -2), (-1, -2)];
+const KING_OFFSETS: [(i8, i8); 8] = [(0, 1), (0, -1), (1, 0), (-1, 0), (1, 1), (-1, -1), (1, -1), (-1, 1)];
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(0, 1), (0, -1), (1, 0), (-1, 0)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (-1, -1), (-1, 1), (1, -1)];
+
+fn offset_attacks(src: Square, offsets: &[(i8, i8)]) -> Bitboard {
+    let mut result = Bitboard::empty();
+    for &(dr, df) in offsets {
+        if let Some(dest) = src.offset(dr, df) {
+            result.set(dest, true);
+        }
+    }
+    result
+}
+
+fn sliding_attacks(occupied: Bitboard, src: Square, directions: &[(i8, i8)]) -> Bitboard {
+    let mut result = Bitboard::empty();
+    for &(dr, df) in directions {
+        let mut dest = src.offset(dr, df);
+        while let Some(square) = dest {
+            result.set(square, true);
+            if occupied.get(square) {
+                break;
+            }
+            dest = square.offset(dr, df);
+        }
+    }
+    result
+}
+
+/// Squares attacked by every pawn in `pawns`, moving up the board if `is_white`.
+pub fn pawn_attacks(pawns: Bitboard, is_white: bool) -> Bitboard {
+    let ahead = pawns.forward(is_white);
+    ahead.east() | ahead.west()
+}
+
+/// Squares attacked by `piece` standing on `src`, including squares occupied by either side.
+pub fn attacks(pos: &Position, src: Square, piece: Piece) -> Bitboard {
+    let occupied = pos.all_pieces();
+    match piece.p_type {
+        PieceType::Pawn => pawn_attacks(Bitboard::from(src), piece.is_white),
+        PieceType::Knight => offset_attacks(src, &KNIGHT_OFFSETS),
+        PieceType::Bishop => sliding_attacks(occupied, src, &BISHOP_DIRECTIONS),
+        PieceType::Rook => sliding_attacks(occupied, src, &ROOK_DIRECTIONS),
+        PieceType::Queen => sliding_attacks(occupied, src, &BISHOP_DIRECTIONS)
+            .union(sliding_attacks(occupied, src, &ROOK_DIRECTIONS)),
+        PieceType::King => offset_attacks(src, &KING_OFFSETS)
+    }
+}
+
+/// Whether any piece of the given color attacks `square`.
+pub fn is_attacked(pos: &Position, square: Square, by_white: bool) -> bool {
+    !attackers_to(pos, square, by_white).is_empty()
+}
+
+/// Pieces of the side `by_white` attacking `square`.
+pub fn attackers_to(pos: &Position, square: Square, by_white: bool) -> Bitboard {
+    let attackers = pos.by_color(by_white);
+    let defender = |p_type| Piece { is_white: !by_white, p_type };
+    let diagonal = pos.bishops.union(pos.queens);
+    let straight = pos.rooks.union(pos.queens);
+    [
+        (PieceType::Pawn, pos.pawns),
+        (PieceType::Knight, pos.knights),
+        (PieceType::Bishop, diagonal),
+        (PieceType::Rook, straight),
+        (PieceType::King, pos.kings)
+    ].iter().fold(Bitboard::empty(), |result, &(p_type, pieces)| {
+        result | (attacks(pos, square, defender(p_type)) & pieces & attackers)
+    })
+}
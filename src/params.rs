@@ -0,0 +1,164 @@
+use std::fmt::Display;
+use std::fs;
+use std::ops::{Index, IndexMut};
+
+use crate::error::Error;
+use crate::eval::Score;
+
+// Indices of each evaluation parameter. Tables occupy a run of consecutive
+// indices starting at their constant, one entry per mobility count.
+pub const PAWN_VALUE: usize = 0;
+pub const KNIGHT_VALUE: usize = 1;
+pub const BISHOP_VALUE: usize = 2;
+pub const ROOK_VALUE: usize = 3;
+pub const QUEEN_VALUE: usize = 4;
+pub const DOUBLED_PAWN: usize = 5;
+pub const ISOLATED_PAWN: usize = 6;
+pub const BISHOP_PAIR: usize = 7;
+pub const BAD_BISHOP_PAWN: usize = 8;
+pub const ROOK_OPEN_FILE: usize = 9;
+pub const ROOK_SEMI_OPEN_FILE: usize = 10;
+pub const ROOK_ON_SEVENTH: usize = 11;
+pub const KNIGHT_OUTPOST: usize = 12;
+pub const KNIGHT_MOBILITY: usize = 13;
+pub const BISHOP_MOBILITY: usize = KNIGHT_MOBILITY + 9;
+pub const ROOK_MOBILITY: usize = BISHOP_MOBILITY + 14;
+pub const QUEEN_MOBILITY: usize = ROOK_MOBILITY + 15;
+pub const PARAM_COUNT: usize = QUEEN_MOBILITY + 28;
+
+/// Name, first index and length of every parameter group, in file order.
+const PARAM_GROUPS: [(&str, usize, usize); 17] = [
+    ("pawn_value", PAWN_VALUE, 1),
+    ("knight_value", KNIGHT_VALUE, 1),
+    ("bishop_value", BISHOP_VALUE, 1),
+    ("rook_value", ROOK_VALUE, 1),
+    ("queen_value", QUEEN_VALUE, 1),
+    ("doubled_pawn", DOUBLED_PAWN, 1),
+    ("isolated_pawn", ISOLATED_PAWN, 1),
+    ("bishop_pair", BISHOP_PAIR, 1),
+    ("bad_bishop_pawn", BAD_BISHOP_PAWN, 1),
+    ("rook_open_file", ROOK_OPEN_FILE, 1),
+    ("rook_semi_open_file", ROOK_SEMI_OPEN_FILE, 1),
+    ("rook_on_seventh", ROOK_ON_SEVENTH, 1),
+    ("knight_outpost", KNIGHT_OUTPOST, 1),
+    ("knight_mobility", KNIGHT_MOBILITY, 9),
+    ("bishop_mobility", BISHOP_MOBILITY, 14),
+    ("rook_mobility", ROOK_MOBILITY, 15),
+    ("queen_mobility", QUEEN_MOBILITY, 28)
+];
+
+const fn s(mg: f32, eg: f32) -> Score {
+    Score { mg, eg }
+}
+
+const DEFAULT_VALUES: [Score; PARAM_COUNT] = [
+    // Material
+    s(1.0, 1.0), s(3.0, 3.0), s(3.0, 3.0), s(5.0, 5.0), s(9.0, 9.0),
+    // Pawn structure
+    s(0.25, 0.25), s(0.5, 0.5),
+    // Bishops
+    s(0.5, 0.5), s(0.04, 0.04),
+    // Rooks
+    s(0.45, 0.45), s(0.2, 0.2), s(0.3, 0.3),
+    // Knights
+    s(0.3, 0.3),
+    // Knight mobility
+    s(-0.62, -0.62), s(-0.53, -0.53), s(-0.12, -0.12), s(-0.04, -0.04), s(0.03, 0.03),
+    s(0.13, 0.13), s(0.22, 0.22), s(0.28, 0.28), s(0.33, 0.33),
+    // Bishop mobility
+    s(-0.48, -0.48), s(-0.20, -0.20), s(0.16, 0.16), s(0.26, 0.26), s(0.38, 0.38),
+    s(0.51, 0.51), s(0.55, 0.55), s(0.63, 0.63), s(0.63, 0.63), s(0.68, 0.68),
+    s(0.81, 0.81), s(0.81, 0.81), s(0.91, 0.91), s(0.98, 0.98),
+    // Rook mobility
+    s(-0.60, -0.60), s(-0.20, -0.20), s(0.02, 0.02), s(0.03, 0.03), s(0.03, 0.03),
+    s(0.11, 0.11), s(0.22, 0.22), s(0.31, 0.31), s(0.40, 0.40), s(0.40, 0.40),
+    s(0.41, 0.41), s(0.48, 0.48), s(0.57, 0.57), s(0.57, 0.57), s(0.62, 0.62),
+    // Queen mobility
+    s(-0.30, -0.30), s(-0.12, -0.12), s(-0.08, -0.08), s(-0.09, -0.09), s(0.20, 0.20),
+    s(0.23, 0.23), s(0.23, 0.23), s(0.35, 0.35), s(0.38, 0.38), s(0.53, 0.53),
+    s(0.64, 0.64), s(0.65, 0.65), s(0.65, 0.65), s(0.66, 0.66), s(0.67, 0.67),
+    s(0.67, 0.67), s(0.72, 0.72), s(0.72, 0.72), s(0.77, 0.77), s(0.79, 0.79),
+    s(0.93, 0.93), s(1.08, 1.08), s(1.08, 1.08), s(1.08, 1.08), s(1.10, 1.10),
+    s(1.14, 1.14), s(1.14, 1.14), s(1.16, 1.16)
+];
+
+/// Every weight used by the evaluation, in pawns. Penalties are stored as
+/// positive values and subtracted where they apply.
+#[derive(Clone, PartialEq)]
+pub struct EvalParams {
+    values: [Score; PARAM_COUNT]
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        Self { values: DEFAULT_VALUES }
+    }
+}
+
+/// Name of the parameter at `index` in parameter files, such as `bishop_pair`
+/// or `rook_mobility[3]`, or `None` past the last parameter.
+fn param_name(index: usize) -> Option<String> {
+    let (name, start, len) = PARAM_GROUPS.into_iter().find(|&(_, start, len)| index >= start && index < start + len)?;
+    Some(if len == 1 { name.to_string() } else { format!("{}[{}]", name, index - start) })
+}
+
+fn param_index(name: &str) -> Option<usize> {
+    (0..PARAM_COUNT).find(|&i| param_name(i).as_deref() == Some(name))
+}
+
+impl EvalParams {
+    /// Parses a parameter file as written by `Display`: one `name mg eg` line
+    /// per parameter. Parameters missing from the file keep their defaults.
+    pub fn parse(text: &str) -> Result<EvalParams, Error> {
+        let mut params = EvalParams::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() != 3 {
+                return Err(Error::Parse(format!("Malformed parameter line '{}'", line)));
+            }
+            let index = param_index(parts[0]).ok_or(Error::Parse(format!("Unknown parameter '{}'", parts[0])))?;
+            let value = |part: &str| part.parse::<f32>().map_err(|e| Error::Parse(format!("Bad value in '{}': {}", line, e)));
+            let (mg, eg) = (value(parts[1])?, value(parts[2])?);
+            params.values[index] = s(mg, eg);
+        }
+        Ok(params)
+    }
+
+    pub fn load(path: &str) -> Result<EvalParams, Error> {
+        let text = fs::read_to_string(path).map_err(|e| Error::Io(format!("Cannot read {}: {}", path, e)))?;
+        Self::parse(&text)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        fs::write(path, self.to_string()).map_err(|e| Error::Io(format!("Cannot write {}: {}", path, e)))
+    }
+}
+
+impl Index<usize> for EvalParams {
+    type Output = Score;
+
+    fn index(&self, index: usize) -> &Score {
+        &self.values[index]
+    }
+}
+
+impl IndexMut<usize> for EvalParams {
+    fn index_mut(&mut self, index: usize) -> &mut Score {
+        &mut self.values[index]
+    }
+}
+
+impl Display for EvalParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, value) in self.values.iter().enumerate() {
+            if let Some(name) = param_name(i) {
+                writeln!(f, "{} {:.4} {:.4}", name, value.mg, value.eg)?;
+            }
+        }
+        Ok(())
+    }
+}
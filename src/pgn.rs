@@ -0,0 +1,342 @@
+use std::fmt::Display;
+use std::fs;
+
+use crate::error::Error;
+use crate::moves::Move;
+use crate::position::Position;
+
+const LINE_WIDTH: usize = 80;
+
+/// A move in the game tree together with its annotations.
+#[derive(Clone)]
+pub struct Node {
+    pub mv: Move,
+    /// Numeric annotation glyphs, `!` is stored as 1, `?` as 2 and so on
+    pub nags: Vec<u8>,
+    /// Comment written before the move, only kept at the start of a line
+    pub comment_before: Option<String>,
+    pub comment: Option<String>,
+    /// Alternatives to this move, each played from the position before it
+    pub variations: Vec<Vec<Node>>
+}
+
+impl Node {
+    pub fn new(mv: Move) -> Node {
+        Node {
+            mv,
+            nags: Vec::new(),
+            comment_before: None,
+            comment: None,
+            variations: Vec::new()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<Node>,
+    pub result: String
+}
+
+impl Game {
+    pub fn new() -> Game {
+        Game {
+            tags: Vec::new(),
+            moves: Vec::new(),
+            result: String::from("*")
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// The position the game starts from, taken from the `FEN` tag if present.
+    pub fn start_position(&self) -> Result<Position, Error> {
+        match self.tag("FEN") {
+            Some(fen) => Position::from_fen(fen),
+            None => Ok(Position::start())
+        }
+    }
+
+    /// Writes the game as PGN text, converting moves to SAN.
+    pub fn to_pgn(&self) -> Result<String, Error> {
+        let mut out = String::new();
+        for (name, value) in &self.tags {
+            out.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+        }
+        if !self.tags.is_empty() {
+            out.push('\n');
+        }
+
+        let mut tokens = Vec::new();
+        write_line(&mut tokens, &self.start_position()?, &self.moves)?;
+        tokens.push(self.result.clone());
+
+        // Attach parentheses to the moves they enclose
+        let mut i = 0;
+        while i < tokens.len() {
+            if tokens[i] == "(" && i + 1 < tokens.len() {
+                let next = tokens.remove(i + 1);
+                tokens[i].push_str(&next);
+            } else if tokens[i] == ")" && i > 0 {
+                let close = tokens.remove(i);
+                tokens[i - 1].push_str(&close);
+                continue;
+            }
+            i += 1;
+        }
+
+        let mut width = 0;
+        for token in tokens {
+            if width > 0 && width + 1 + token.len() > LINE_WIDTH {
+                out.push('\n');
+                width = 0;
+            } else if width > 0 {
+                out.push(' ');
+                width += 1;
+            }
+            width += token.len();
+            out.push_str(&token);
+        }
+        out.push('\n');
+        Ok(out)
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for Game {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_pgn().map_err(|_| std::fmt::Error)?)
+    }
+}
+
+fn write_line(tokens: &mut Vec<String>, start: &Position, nodes: &[Node]) -> Result<(), Error> {
+    let mut pos = start.snapshot();
+    let mut needs_number = true;
+    for node in nodes {
+        if let Some(comment) = &node.comment_before {
+            tokens.push(format!("{{{}}}", comment));
+        }
+        if pos.white_to_play {
+            tokens.push(format!("{}.", pos.fullmove_number));
+        } else if needs_number {
+            tokens.push(format!("{}...", pos.fullmove_number));
+        }
+        tokens.push(pos.to_san(node.mv)?);
+        tokens.extend(node.nags.iter().map(|nag| format!("${}", nag)));
+        needs_number = false;
+        if let Some(comment) = &node.comment {
+            tokens.push(format!("{{{}}}", comment));
+            needs_number = true;
+        }
+        for variation in &node.variations {
+            tokens.push(String::from("("));
+            write_line(tokens, &pos, variation)?;
+            tokens.push(String::from(")"));
+            needs_number = true;
+        }
+        pos.do_move(node.mv)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    Open,
+    Close,
+    Result(String),
+    San(String)
+}
+
+fn symbolic_nag(s: &str) -> Option<u8> {
+    match s {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Error> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            // Escape lines are ignored
+            '%' if i == 0 || chars[i - 1] == '\n' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            },
+            '[' => {
+                let end = chars[i..].iter().position(|&c| c == ']').ok_or(Error::Parse(String::from("Unterminated tag")))? + i;
+                let inner: String = chars[i + 1..end].iter().collect();
+                let (name, value) = inner.trim().split_once(char::is_whitespace).ok_or_else(|| Error::Parse(format!("Invalid tag [{}]", inner)))?;
+                let value = value.trim().trim_matches('"').replace("\\\"", "\"").replace("\\\\", "\\");
+                tokens.push(Token::Tag(name.to_string(), value));
+                i = end + 1;
+            },
+            '{' => {
+                let end = chars[i..].iter().position(|&c| c == '}').ok_or(Error::Parse(String::from("Unterminated comment")))? + i;
+                let comment: String = chars[i + 1..end].iter().collect();
+                tokens.push(Token::Comment(comment.split_whitespace().collect::<Vec<_>>().join(" ")));
+                i = end + 1;
+            },
+            ';' => {
+                let end = chars[i..].iter().position(|&c| c == '\n').map_or(chars.len(), |p| p + i);
+                let comment: String = chars[i + 1..end].iter().collect();
+                tokens.push(Token::Comment(comment.trim().to_string()));
+                i = end;
+            },
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            },
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            },
+            _ => {
+                let end = chars[i..].iter()
+                    .position(|&c| c.is_whitespace() || "[]{}();".contains(c))
+                    .map_or(chars.len(), |p| p + i);
+                let word: String = chars[i..end].iter().collect();
+                i = end;
+                tokenize_word(&mut tokens, &word)?;
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn tokenize_word(tokens: &mut Vec<Token>, word: &str) -> Result<(), Error> {
+    if matches!(word, "1-0" | "0-1" | "1/2-1/2" | "*") {
+        tokens.push(Token::Result(word.to_string()));
+        return Ok(());
+    }
+    if let Some(nag) = word.strip_prefix('$') {
+        tokens.push(Token::Nag(nag.parse().map_err(|_| Error::Parse(format!("Invalid NAG {}", word)))?));
+        return Ok(());
+    }
+    if let Some(nag) = symbolic_nag(word) {
+        tokens.push(Token::Nag(nag));
+        return Ok(());
+    }
+
+    // Move numbers may be glued to the move, as in `12.Nf3` or `12...Nf3`
+    let san = word.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let san = if san.len() < word.len() && !word[..word.len() - san.len()].contains('.') { word } else { san };
+    if san.is_empty() {
+        return Ok(());
+    }
+    let annotation_start = san.find(['!', '?']).unwrap_or(san.len());
+    tokens.push(Token::San(san[..annotation_start].to_string()));
+    if annotation_start < san.len() {
+        let annotation = &san[annotation_start..];
+        tokens.push(Token::Nag(symbolic_nag(annotation).ok_or_else(|| Error::Parse(format!("Invalid annotation {}", annotation)))?));
+    }
+    Ok(())
+}
+
+fn append_comment(target: &mut Option<String>, comment: String) {
+    match target {
+        Some(existing) => {
+            existing.push(' ');
+            existing.push_str(&comment);
+        },
+        None => *target = Some(comment)
+    }
+}
+
+/// Reads one line of moves starting at `tokens[*index]`, stopping at the end
+/// of a variation, a result or the next game's tags.
+fn parse_line(tokens: &[Token], index: &mut usize, start: &Position) -> Result<Vec<Node>, Error> {
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut pos = start.snapshot();
+    let mut prev = start.snapshot();
+    let mut pending_comment = None;
+    while let Some(token) = tokens.get(*index) {
+        match token {
+            Token::Tag(..) | Token::Result(_) | Token::Close => break,
+            Token::Comment(comment) => match nodes.last_mut() {
+                Some(node) => append_comment(&mut node.comment, comment.clone()),
+                None => append_comment(&mut pending_comment, comment.clone())
+            },
+            Token::Nag(nag) => nodes.last_mut().ok_or(Error::Parse(String::from("Annotation before the first move")))?.nags.push(*nag),
+            Token::Open => {
+                *index += 1;
+                let variation = parse_line(tokens, index, &prev)?;
+                if tokens.get(*index) != Some(&Token::Close) {
+                    return Err(Error::Parse(String::from("Unterminated variation")));
+                }
+                nodes.last_mut().ok_or(Error::Parse(String::from("Variation before the first move")))?.variations.push(variation);
+            },
+            Token::San(san) => {
+                let mv = pos.parse_san(san).map_err(|e| Error::Parse(format!("{} after {}", e, pos.to_fen())))?;
+                let mut node = Node::new(mv);
+                node.comment_before = pending_comment.take();
+                nodes.push(node);
+                prev = pos.snapshot();
+                pos.do_move(mv)?;
+            }
+        }
+        *index += 1;
+    }
+    if let Some(comment) = pending_comment
+        && let Some(first) = nodes.first_mut() {
+        first.comment_before = Some(comment);
+    }
+    Ok(nodes)
+}
+
+/// Reads every game in a PGN text.
+pub fn read_games(text: &str) -> Result<Vec<Game>, Error> {
+    let tokens = tokenize(text)?;
+    let mut games = Vec::new();
+    let mut index = 0;
+    while index < tokens.len() {
+        let mut game = Game::new();
+        while let Some(Token::Tag(name, value)) = tokens.get(index) {
+            game.tags.push((name.clone(), value.clone()));
+            index += 1;
+        }
+        let start = game.start_position()?;
+        game.moves = parse_line(&tokens, &mut index, &start)?;
+        match tokens.get(index) {
+            Some(Token::Result(result)) => {
+                game.result = result.clone();
+                index += 1;
+            },
+            Some(Token::Close) => return Err(Error::Parse(String::from("Unmatched ')'"))),
+            _ => game.result = game.tag("Result").unwrap_or("*").to_string()
+        }
+        games.push(game);
+    }
+    Ok(games)
+}
+
+/// `pgn <file>`: reads a PGN file and writes it back out in export format.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let path = args.first().ok_or(Error::Parse(String::from("Usage: pgn <file>")))?;
+    let text = fs::read_to_string(path).map_err(|e| Error::Io(format!("Cannot read {}: {}", path, e)))?;
+    for game in read_games(&text)? {
+        println!("{}", game.to_pgn()?);
+    }
+    Ok(())
+}
@@ -24,6 +24,12 @@ impl Piece {
     pub fn black(p_type: PieceType) -> Piece {
         Piece { is_white: false, p_type }
     }
+
+    /// The chess symbol for the piece, outlined for white and filled for black.
+    pub fn to_unicode(&self) -> char {
+        let symbols = if self.is_white { ['♙', '♘', '♗', '♖', '♕', '♔'] } else { ['♟', '♞', '♝', '♜', '♛', '♚'] };
+        symbols[self.p_type as usize]
+    }
 }
 
 impl Display for Piece {
@@ -0,0 +1,345 @@
+use std::fs;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::engine_match::{adjudicate, Outcome};
+use crate::error::Error;
+use crate::moves::Move;
+use crate::pgn::{Game, Node};
+use crate::position::Position;
+use crate::search::{search, time_budget, SearchContext, SearchLimits};
+use crate::square::{Square, FILE_NAMES, RANK_NAMES};
+
+const HELP: &str = "\
+Enter moves in SAN (Nf3, exd5, O-O) or coordinates (g1f3, e7e8q). Commands:
+  undo         take back one move and stop the engine, so you play both sides
+  takeback     take back your last move and the engine's reply
+  go           let the engine play the side to move from here on
+  hint         suggest a move
+  flip         turn the board around
+  fen          print the position as FEN
+  pgn [file]   print the game as PGN, or save it to a file
+  help         show this text
+  quit         leave";
+
+struct PlayOptions {
+    human_white: bool,
+    fen: Option<String>,
+    /// Base time and increment per move for both sides, no clock without it
+    clock: Option<(Duration, Duration)>,
+    /// Engine thinking time per move when there is no clock
+    movetime: Duration,
+    depth: Option<i32>,
+    unicode: bool
+}
+
+fn parse_options(args: &[String]) -> Result<PlayOptions, Error> {
+    let mut options = PlayOptions {
+        human_white: true,
+        fen: None,
+        clock: None,
+        movetime: Duration::from_secs(2),
+        depth: None,
+        unicode: false
+    };
+    let seconds = |s: &str| -> Result<Duration, Error> {
+        s.parse::<f64>().ok().filter(|s| *s >= 0.0).map(Duration::from_secs_f64).ok_or(Error::Parse(format!("Invalid time '{}'", s)))
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or(Error::Parse(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--color" => options.human_white = match value()?.as_str() {
+                "white" => true,
+                "black" => false,
+                c => return Err(Error::Parse(format!("Unknown color '{}', expected white or black", c)))
+            },
+            "--fen" => options.fen = Some(value()?),
+            "--tc" => {
+                let tc = value()?;
+                let (base, increment) = tc.split_once('+').unwrap_or((&tc, "0"));
+                options.clock = Some((seconds(base)?, seconds(increment)?));
+            },
+            "--movetime" => options.movetime = seconds(&value()?)?,
+            "--depth" => options.depth = Some(value()?.parse().map_err(|_| Error::Parse(String::from("Invalid depth")))?),
+            "--unicode" => options.unicode = true,
+            _ => return Err(Error::Parse(format!("Unexpected argument '{}'", arg)))
+        }
+    }
+    Ok(options)
+}
+
+/// The board as text, from black's side if `flipped`.
+fn board_text(pos: &Position, unicode: bool, flipped: bool) -> String {
+    let ranks: Vec<i8> = if flipped { (0..8).collect() } else { (0..8).rev().collect() };
+    let files: Vec<i8> = if flipped { (0..8).rev().collect() } else { (0..8).collect() };
+    let mut text = String::new();
+    for &rank in &ranks {
+        text.push(RANK_NAMES[rank as usize]);
+        text.push(' ');
+        for &file in &files {
+            text.push(' ');
+            text.push(match pos.piece_on(Square::new(rank, file)) {
+                Some(p) if unicode => p.to_unicode(),
+                Some(p) => p.to_string().chars().next().unwrap_or('?'),
+                None => '.'
+            });
+        }
+        text.push('\n');
+    }
+    text.push_str("  ");
+    for &file in &files {
+        text.push(' ');
+        text.push(FILE_NAMES[file as usize]);
+    }
+    text
+}
+
+fn format_clock(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// A game between the user and the engine.
+struct Session {
+    options: PlayOptions,
+    ctx: SearchContext,
+    start: Position,
+    pos: Position,
+    moves: Vec<Move>,
+    /// Keys of every position so far, for repetitions
+    keys: Vec<u64>,
+    /// Which side the engine plays, or `None` while the user plays both
+    engine_white: Option<bool>,
+    flipped: bool,
+    clocks: [Duration; 2],
+    turn_start: Instant,
+    outcome: Option<Outcome>
+}
+
+impl Session {
+    fn new(options: PlayOptions) -> Result<Session, Error> {
+        let start = match &options.fen {
+            Some(fen) => Position::from_fen(fen)?,
+            None => Position::start()
+        };
+        let base = options.clock.map_or(Duration::ZERO, |(base, _)| base);
+        Ok(Session {
+            ctx: SearchContext::new(),
+            pos: start.clone(),
+            keys: vec![start.polyglot_key()],
+            start,
+            moves: Vec::new(),
+            engine_white: Some(!options.human_white),
+            flipped: !options.human_white,
+            clocks: [base; 2],
+            turn_start: Instant::now(),
+            outcome: None,
+            options
+        })
+    }
+
+    fn show(&self) {
+        println!();
+        println!("{}", board_text(&self.pos, self.options.unicode, self.flipped));
+        if self.options.clock.is_some() {
+            println!("White {}  Black {}", format_clock(self.clocks[0]), format_clock(self.clocks[1]));
+        }
+        if let Some(outcome) = &self.outcome {
+            println!("{} {{{}}}", outcome.result, outcome.termination);
+        }
+    }
+
+    fn engine_to_play(&self) -> bool {
+        self.outcome.is_none() && self.engine_white == Some(self.pos.white_to_play)
+    }
+
+    /// Plays `mv`, charging the time since the last move to the side that
+    /// made it, then checks whether the game is over.
+    fn play(&mut self, mv: Move) -> Result<(), Error> {
+        let side = if self.pos.white_to_play { 0 } else { 1 };
+        if let Some((_, increment)) = self.options.clock {
+            let elapsed = self.turn_start.elapsed();
+            if elapsed > self.clocks[side] {
+                self.clocks[side] = Duration::ZERO;
+                self.outcome = Some(Outcome::win(side == 1, String::from("time forfeit")));
+                return Ok(());
+            }
+            self.clocks[side] = self.clocks[side] - elapsed + increment;
+        }
+        let san = self.pos.to_san(mv)?;
+        println!("{}{} {}", self.pos.fullmove_number, if side == 0 { "." } else { "..." }, san);
+        self.pos.do_move(mv)?;
+        self.moves.push(mv);
+        self.keys.push(self.pos.polyglot_key());
+        self.turn_start = Instant::now();
+        self.outcome = adjudicate(&self.pos, &self.keys)?;
+        Ok(())
+    }
+
+    /// Takes back up to `plies` moves, reporting how many there were.
+    fn undo(&mut self, plies: usize) -> Result<usize, Error> {
+        let plies = plies.min(self.moves.len());
+        for _ in 0..plies {
+            self.pos.undo_move()?;
+            self.moves.pop();
+            self.keys.pop();
+        }
+        self.outcome = None;
+        self.turn_start = Instant::now();
+        Ok(plies)
+    }
+
+    fn limits(&self) -> SearchLimits {
+        let time = match self.options.clock {
+            Some((_, increment)) => {
+                let side = if self.pos.white_to_play { 0 } else { 1 };
+                time_budget(self.clocks[side].saturating_sub(self.turn_start.elapsed()), increment)
+            },
+            None => self.options.movetime
+        };
+        SearchLimits { depth: self.options.depth, time: Some(time), ..SearchLimits::default() }
+    }
+
+    fn engine_move(&mut self) -> Result<(), Error> {
+        let limits = self.limits();
+        let result = search(&mut self.ctx, &mut self.pos.snapshot(), &limits)?;
+        let Some(mv) = result.best_move else {
+            return Err(Error::IllegalMove(String::from("The engine found no move")));
+        };
+        println!("Engine: depth {}, score {:+.2}, {} nodes", result.depth, result.score, result.nodes);
+        self.play(mv)
+    }
+
+    /// Searches for the side to move without playing anything. The time is
+    /// not taken from the clock, so it is kept short.
+    fn hint(&mut self) -> Result<(), Error> {
+        let mut limits = self.limits();
+        limits.time = limits.time.map(|time| time.min(Duration::from_secs(1)));
+        let result = search(&mut self.ctx, &mut self.pos.snapshot(), &limits)?;
+        match result.best_move {
+            Some(mv) => println!("Hint: {} ({:+.2})", self.pos.to_san(mv)?, result.score),
+            None => println!("No legal moves")
+        }
+        Ok(())
+    }
+
+    fn to_pgn(&self) -> Result<String, Error> {
+        let name = |white: bool| if self.engine_white == Some(white) { "CrabGambit" } else { "Human" };
+        let mut game = Game::new();
+        if let Some(outcome) = &self.outcome {
+            game.result = outcome.result.to_string();
+        }
+        game.tags = vec![
+            (String::from("Event"), String::from("Casual game")),
+            (String::from("Site"), String::from("?")),
+            (String::from("Date"), String::from("????.??.??")),
+            (String::from("Round"), String::from("-")),
+            (String::from("White"), name(true).to_string()),
+            (String::from("Black"), name(false).to_string()),
+            (String::from("Result"), game.result.clone())
+        ];
+        if let Some((base, increment)) = self.options.clock {
+            game.tags.push((String::from("TimeControl"), format!("{}+{}", base.as_secs_f64(), increment.as_secs_f64())));
+        }
+        let start_fen = self.start.to_fen();
+        if start_fen != Position::start().to_fen() {
+            game.tags.push((String::from("SetUp"), String::from("1")));
+            game.tags.push((String::from("FEN"), start_fen));
+        }
+        if let Some(outcome) = &self.outcome {
+            game.tags.push((String::from("Termination"), outcome.termination.clone()));
+        }
+        game.moves = self.moves.iter().map(|&mv| Node::new(mv)).collect();
+        game.to_pgn()
+    }
+
+    /// Handles one line from the user, returning false to quit.
+    fn command(&mut self, line: &str) -> Result<bool, Error> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            [] => {},
+            ["quit" | "exit"] => return Ok(false),
+            ["help"] => println!("{}", HELP),
+            ["undo"] => {
+                if self.undo(1)? == 0 {
+                    println!("No move to undo");
+                }
+                self.engine_white = None;
+                println!("You now play both sides, enter go to let the engine take over");
+                self.show();
+            },
+            ["takeback"] => {
+                // Back to the last position where it was the user's turn
+                let plies = match self.engine_white {
+                    Some(engine_white) if self.pos.white_to_play == engine_white => 1,
+                    Some(_) => 2,
+                    None => 1
+                };
+                if self.undo(plies)? == 0 {
+                    println!("No move to take back");
+                }
+                self.show();
+            },
+            ["go"] => self.engine_white = Some(self.pos.white_to_play),
+            ["hint"] => self.hint()?,
+            ["flip"] => {
+                self.flipped = !self.flipped;
+                self.show();
+            },
+            ["fen"] => println!("{}", self.pos.to_fen()),
+            ["pgn"] => print!("{}", self.to_pgn()?),
+            ["pgn", path] => {
+                fs::write(path, self.to_pgn()?).map_err(|e| Error::Io(format!("Cannot write {}: {}", path, e)))?;
+                println!("Saved to {}", path);
+            },
+            [text] if self.outcome.is_some() => println!("The game is over, '{}' was not played", text),
+            [text] => {
+                let parsed = match self.pos.parse_legal_move(text) {
+                    Err(Error::Parse(_)) => self.pos.parse_san(text),
+                    parsed => parsed
+                };
+                match parsed {
+                    Ok(mv) => {
+                        self.play(mv)?;
+                        self.show();
+                    },
+                    Err(e) => println!("{}", e)
+                }
+            },
+            _ => println!("Unknown command '{}', try help", line.trim())
+        }
+        Ok(true)
+    }
+}
+
+/// `--play [--color white|black] [--fen <fen>] [--tc base+inc] [--movetime s]
+/// [--depth n] [--unicode]`: a game against the engine in the terminal.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let mut session = Session::new(parse_options(args)?)?;
+    println!("{}", HELP);
+    session.show();
+
+    let input = io::stdin();
+    let mut line = String::new();
+    loop {
+        if session.engine_to_play() {
+            session.engine_move()?;
+            session.show();
+            continue;
+        }
+        let side = if session.pos.white_to_play { "White" } else { "Black" };
+        print!("{}. {} to move> ", session.pos.fullmove_number, side);
+        io::stdout().flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+        match session.command(&line) {
+            Ok(true) => {},
+            Ok(false) => return Ok(()),
+            Err(e) => println!("{}", e)
+        }
+    }
+}
@@ -1,16 +1,51 @@
 use std::fmt::Display;
 use crate::bitboard::Bitboard;
+use crate::error::{Error, PositionError};
 use crate::square::{Square, RANK_NAMES, FILE_NAMES};
 use crate::piece::{Piece, PieceType};
-use crate::moves::{generate_moves, Move, PastMove};
+use crate::moves::{attackers_to, generate_legal_moves, is_attacked, Move, MoveList};
+use crate::polyglot::{RANDOM64, CASTLING, EN_PASSANT, TURN};
 
 #[derive(Clone, Copy, PartialEq)]
 pub struct CastleState {
     pub can_short_castle: bool,
-    pub can_long_castle: bool
+    pub can_long_castle: bool,
+    /// Files the castling rooks start on, only other than 7 and 0 in Chess960
+    pub short_rook_file: i8,
+    pub long_rook_file: i8
 }
 
-#[derive(Clone, PartialEq)]
+impl CastleState {
+    fn new(can_castle: bool) -> CastleState {
+        CastleState {
+            can_short_castle: can_castle,
+            can_long_castle: can_castle,
+            short_rook_file: 7,
+            long_rook_file: 0
+        }
+    }
+
+    pub fn rook_file(&self, short: bool) -> i8 {
+        if short { self.short_rook_file } else { self.long_rook_file }
+    }
+}
+
+/// What `undo_move` needs to take a move back, saved before each move.
+#[derive(Clone)]
+struct State {
+    mv: Move,
+    captured: Option<Piece>,
+    white_castle_state: CastleState,
+    black_castle_state: CastleState,
+    en_passant_target: Option<Square>,
+    halfmove_clock: u32,
+    hash: u64,
+    checkers: Bitboard
+}
+
+/// Cloning keeps the moves that led to the position so they can be undone,
+/// use `snapshot` for a copy that only probes moves from here.
+#[derive(Clone)]
 pub struct Position {
     pub white_to_play: bool,
     pub en_passant_target: Option<Square>,
@@ -23,16 +58,29 @@ pub struct Position {
     pub bishops: Bitboard,
     pub rooks: Bitboard,
     pub queens: Bitboard,
-    pub kings: Bitboard
+    pub kings: Bitboard,
+    /// The piece on every square, kept in step with the bitboards
+    board: [Option<Piece>; 64],
+    /// Plies since the last capture or pawn move, for the fifty-move rule
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+    /// Castling moves are written as the king taking its own rook
+    pub chess960: bool,
+    /// Polyglot key, updated as pieces move
+    hash: u64,
+    /// Pieces giving check to the side to move
+    checkers: Bitboard,
+    /// One entry per move played, most recent last
+    states: Vec<State>
 }
 
 impl Position {
     pub fn empty() -> Position {
-        Position {
+        let mut pos = Position {
             white_to_play: true,
             en_passant_target: None,
-            white_castle_state: CastleState { can_short_castle: true, can_long_castle: true },
-            black_castle_state: CastleState { can_short_castle: true, can_long_castle: true },
+            white_castle_state: CastleState::new(true),
+            black_castle_state: CastleState::new(true),
             white_pieces: Bitboard::empty(),
             black_pieces: Bitboard::empty(),
             pawns: Bitboard::empty(),
@@ -40,7 +88,42 @@ impl Position {
             bishops: Bitboard::empty(),
             rooks: Bitboard::empty(),
             queens: Bitboard::empty(),
-            kings: Bitboard::empty()
+            kings: Bitboard::empty(),
+            board: [None; 64],
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            chess960: false,
+            hash: 0,
+            checkers: Bitboard::empty(),
+            states: Vec::new()
+        };
+        pos.hash = pos.compute_hash();
+        pos
+    }
+
+    /// A copy without the moves that led here, so it cannot undo past this
+    /// point. Much cheaper than `clone` deep in a game.
+    pub fn snapshot(&self) -> Position {
+        Position {
+            white_to_play: self.white_to_play,
+            en_passant_target: self.en_passant_target,
+            white_castle_state: self.white_castle_state,
+            black_castle_state: self.black_castle_state,
+            white_pieces: self.white_pieces,
+            black_pieces: self.black_pieces,
+            pawns: self.pawns,
+            knights: self.knights,
+            bishops: self.bishops,
+            rooks: self.rooks,
+            queens: self.queens,
+            kings: self.kings,
+            board: self.board,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            chess960: self.chess960,
+            hash: self.hash,
+            checkers: self.checkers,
+            states: Vec::new()
         }
     }
 
@@ -55,28 +138,350 @@ impl Position {
             PieceType::Knight,
             PieceType::Rook
         ];
+        Self::with_back_rank(&BACK_RANK)
+    }
+
+    /// Chess960 starting position number `index`, from 0 to 959, in the
+    /// standard numbering where 518 is the usual setup.
+    pub fn start960(index: u32) -> Result<Position, Error> {
+        if index >= 960 {
+            return Err(PositionError::UnknownChess960Index(index).into());
+        }
+        const KNIGHTS: [(usize, usize); 10] = [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4)];
+        let mut back_rank: [Option<PieceType>; 8] = [None; 8];
+        let mut n = index as usize;
+        back_rank[2 * (n % 4) + 1] = Some(PieceType::Bishop);
+        n /= 4;
+        back_rank[2 * (n % 4)] = Some(PieceType::Bishop);
+        n /= 4;
+
+        // The rest are placed on the files still empty, counted from the a-file
+        let place = |back_rank: &mut [Option<PieceType>; 8], nth: usize, p_type: PieceType| {
+            let file = (0..8).filter(|&f| back_rank[f].is_none()).nth(nth).unwrap();
+            back_rank[file] = Some(p_type);
+        };
+        place(&mut back_rank, n % 6, PieceType::Queen);
+        n /= 6;
+        let (first, second) = KNIGHTS[n];
+        place(&mut back_rank, second, PieceType::Knight);
+        place(&mut back_rank, first, PieceType::Knight);
+        for p_type in [PieceType::Rook, PieceType::King, PieceType::Rook] {
+            place(&mut back_rank, 0, p_type);
+        }
+
+        let back_rank = back_rank.map(Option::unwrap);
+        let mut pos = Self::with_back_rank(&back_rank);
+        let rook_files: Vec<i8> = (0..8).filter(|&f| back_rank[f as usize] == PieceType::Rook).collect();
+        for state in [&mut pos.white_castle_state, &mut pos.black_castle_state] {
+            state.long_rook_file = rook_files[0];
+            state.short_rook_file = rook_files[1];
+        }
+        pos.chess960 = true;
+        Ok(pos)
+    }
+
+    fn with_back_rank(back_rank: &[PieceType; 8]) -> Position {
         let mut pos = Self::empty();
         for file in 0..8 {
-            pos.set_piece(Square { rank: 0, file }, Piece::white(BACK_RANK[file as usize]));
-            pos.set_piece(Square { rank: 1, file }, Piece::white(PieceType::Pawn));
-            pos.set_piece(Square { rank: 6, file }, Piece::black(PieceType::Pawn));
-            pos.set_piece(Square { rank: 7, file }, Piece::black(BACK_RANK[file as usize]));
+            pos.set_piece(Square::new(0, file), Piece::white(back_rank[file as usize]));
+            pos.set_piece(Square::new(1, file), Piece::white(PieceType::Pawn));
+            pos.set_piece(Square::new(6, file), Piece::black(PieceType::Pawn));
+            pos.set_piece(Square::new(7, file), Piece::black(back_rank[file as usize]));
         }
+        pos.hash = pos.compute_hash();
         pos
     }
 
+    /// Parses a FEN string. The move clocks are optional and default to `0 1`.
+    pub fn from_fen(fen: &str) -> Result<Position, Error> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(Error::Parse(format!("FEN '{}' has too few fields", fen)));
+        }
+
+        let mut pos = Self::empty();
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(Error::Parse(format!("FEN '{}' does not have 8 ranks", fen)));
+        }
+        for (i, rank_str) in ranks.iter().enumerate() {
+            let rank = 7 - i as i8;
+            let mut file = 0i8;
+            for c in rank_str.chars() {
+                if let Some(skip) = c.to_digit(10) {
+                    file = file.saturating_add(skip as i8);
+                    continue;
+                }
+                let p_type = match c.to_ascii_lowercase() {
+                    'p' => PieceType::Pawn,
+                    'n' => PieceType::Knight,
+                    'b' => PieceType::Bishop,
+                    'r' => PieceType::Rook,
+                    'q' => PieceType::Queen,
+                    'k' => PieceType::King,
+                    _ => return Err(Error::Parse(format!("Invalid piece '{}' in FEN '{}'", c, fen)))
+                };
+                let Some(square) = Square::try_new(rank, file) else {
+                    return Err(Error::Parse(format!("Rank {} is too long in FEN '{}'", RANK_NAMES[rank as usize], fen)));
+                };
+                pos.set_piece(square, Piece { is_white: c.is_ascii_uppercase(), p_type });
+                file += 1;
+            }
+            if file != 8 {
+                return Err(Error::Parse(format!("Rank {} does not have 8 files in FEN '{}'", RANK_NAMES[rank as usize], fen)));
+            }
+        }
+
+        pos.white_to_play = match fields[1] {
+            "w" => true,
+            "b" => false,
+            _ => return Err(Error::Parse(format!("Invalid side to play '{}' in FEN", fields[1])))
+        };
+
+        // Castling rights are KQkq for the outermost rooks, as in X-FEN, or
+        // the rook's file as in Shredder-FEN
+        pos.white_castle_state = CastleState::new(false);
+        pos.black_castle_state = CastleState::new(false);
+        if fields[2] != "-" {
+            for c in fields[2].chars() {
+                let is_white = c.is_ascii_uppercase();
+                let rank = if is_white { 0 } else { 7 };
+                let Some(king_file) = pos.home_king_file(is_white) else {
+                    return Err(PositionError::ImpossibleCastlingRights { white: is_white }.into());
+                };
+                let our_rooks = pos.rooks.intersect(pos.by_color(is_white));
+                let rook_file = match c.to_ascii_lowercase() {
+                    'k' => (king_file + 1..8).rev().find(|&file| our_rooks.get(Square::new(rank, file))).unwrap_or(7),
+                    'q' => (0..king_file).find(|&file| our_rooks.get(Square::new(rank, file))).unwrap_or(0),
+                    f @ 'a'..='h' => FILE_NAMES.iter().position(|&n| n == f).unwrap() as i8,
+                    _ => return Err(Error::Parse(format!("Invalid castling rights '{}' in FEN", fields[2])))
+                };
+                let state = if is_white { &mut pos.white_castle_state } else { &mut pos.black_castle_state };
+                if rook_file > king_file {
+                    state.can_short_castle = true;
+                    state.short_rook_file = rook_file;
+                } else {
+                    state.can_long_castle = true;
+                    state.long_rook_file = rook_file;
+                }
+                if king_file != 4 || (rook_file != 0 && rook_file != 7) {
+                    pos.chess960 = true;
+                }
+            }
+        }
+
+        // FEN names the square skipped by the pawn, we track the pawn itself
+        if fields[3] != "-" {
+            let skipped: Square = fields[3].parse()?;
+            if skipped.rank() != 2 && skipped.rank() != 5 {
+                return Err(Error::Parse(format!("Invalid en passant square '{}' in FEN", fields[3])));
+            }
+            let direction = if skipped.rank() == 2 { 1 } else { -1 };
+            pos.en_passant_target = Some(Square::new(skipped.rank() + direction, skipped.file()));
+        }
+
+        if let Some(halfmove) = fields.get(4) {
+            pos.halfmove_clock = halfmove.parse().map_err(|_| Error::Parse(format!("Invalid halfmove clock '{}' in FEN", halfmove)))?;
+        }
+        if let Some(fullmove) = fields.get(5) {
+            pos.fullmove_number = fullmove.parse().map_err(|_| Error::Parse(format!("Invalid fullmove number '{}' in FEN", fullmove)))?;
+        }
+
+        pos.validate()?;
+        pos.hash = pos.compute_hash();
+        pos.checkers = pos.find_checkers();
+        Ok(pos)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                if let Some(p) = self.get_peice(Square::new(rank, file)) {
+                    if empty > 0 {
+                        fen.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    fen.push_str(&p.to_string());
+                } else {
+                    empty += 1;
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.white_to_play { " w " } else { " b " });
+
+        // X-FEN: a file letter only where KQkq would name a different rook
+        let mut castling = String::new();
+        for (is_white, short, letter) in [(true, true, 'K'), (true, false, 'Q'), (false, true, 'k'), (false, false, 'q')] {
+            let state = self.castle_state(is_white);
+            if !(if short { state.can_short_castle } else { state.can_long_castle }) {
+                continue;
+            }
+            let rank = if is_white { 0 } else { 7 };
+            let rook_file = state.rook_file(short);
+            let our_rooks = self.rooks.intersect(self.by_color(is_white));
+            let outer_files: Vec<i8> = if short { (rook_file + 1..8).collect() } else { (0..rook_file).collect() };
+            if outer_files.iter().any(|&file| our_rooks.get(Square::new(rank, file))) {
+                let file = FILE_NAMES[rook_file as usize];
+                castling.push(if is_white { file.to_ascii_uppercase() } else { file });
+            } else {
+                castling.push(letter);
+            }
+        }
+        fen.push_str(if castling.is_empty() { "-" } else { &castling });
+
+        match self.en_passant_target {
+            Some(target) => {
+                let direction = if target.rank() == 3 { -1 } else { 1 };
+                fen.push_str(&format!(" {} ", Square::new(target.rank() + direction, target.file())));
+            }
+            None => fen.push_str(" - ")
+        }
+
+        fen.push_str(&format!("{} {}", self.halfmove_clock, self.fullmove_number));
+        fen
+    }
+
+    /// Hash of the position as used by Polyglot opening books.
+    pub fn polyglot_key(&self) -> u64 {
+        self.hash
+    }
+
+    fn compute_hash(&self) -> u64 {
+        let mut key = self.state_key();
+        for square in Square::all() {
+            if let Some(p) = self.piece_on(square) {
+                key ^= piece_key(square, p);
+            }
+        }
+        key
+    }
+
+    /// The part of the hash that is not piece placement: castling, en
+    /// passant and the side to move.
+    fn state_key(&self) -> u64 {
+        let mut key = 0u64;
+
+        // A right only counts while the rook is still in its corner
+        let rights = [
+            (self.white_castle_state.can_short_castle, Square::new(0, 7), Piece::white(PieceType::Rook)),
+            (self.white_castle_state.can_long_castle, Square::new(0, 0), Piece::white(PieceType::Rook)),
+            (self.black_castle_state.can_short_castle, Square::new(7, 7), Piece::black(PieceType::Rook)),
+            (self.black_castle_state.can_long_castle, Square::new(7, 0), Piece::black(PieceType::Rook))
+        ];
+        for (i, (allowed, corner, rook)) in rights.into_iter().enumerate() {
+            if allowed && self.get_peice(corner) == Some(rook) {
+                key ^= RANDOM64[CASTLING + i];
+            }
+        }
+
+        // En passant only counts if a pawn can actually capture
+        if let Some(target) = self.en_passant_target {
+            let our_pawns = self.pawns.intersect(self.by_color(self.white_to_play));
+            let can_capture = [-1, 1].iter()
+                .filter_map(|&files| target.offset(0, files))
+                .any(|sq| our_pawns.get(sq));
+            if can_capture {
+                key ^= RANDOM64[EN_PASSANT + target.file() as usize];
+            }
+        }
+
+        if self.white_to_play {
+            key ^= RANDOM64[TURN];
+        }
+        key
+    }
+
+    pub fn castle_state(&self, white: bool) -> &CastleState {
+        if white { &self.white_castle_state } else { &self.black_castle_state }
+    }
+
+    fn castle_state_mut(&mut self, white: bool) -> &mut CastleState {
+        if white { &mut self.white_castle_state } else { &mut self.black_castle_state }
+    }
+
+    /// File of the king if it stands on its home rank.
+    fn home_king_file(&self, white: bool) -> Option<i8> {
+        let rank = if white { 0 } else { 7 };
+        let kings = self.kings.intersect(self.by_color(white));
+        (0..8).find(|&file| kings.get(Square::new(rank, file)))
+    }
+
+    /// Resolves a move in UCI notation against the legal moves. Castling is
+    /// accepted both as the king's destination, `e1g1`, and as the king taking
+    /// its own rook, `e1h1`, whether or not this is a Chess960 game.
+    pub fn parse_legal_move(&self, uci: &str) -> Result<Move, Error> {
+        let mv: Move = uci.parse()?;
+        let mut legal = MoveList::new();
+        generate_legal_moves(&mut legal, &mut self.snapshot())?;
+        // An exact match wins, as a king step can look like a castle in Chess960
+        if let Some(&exact) = legal.iter().find(|m| m.same_squares(mv)) {
+            return Ok(exact);
+        }
+        let is_white = self.white_to_play;
+        legal.into_iter()
+            .find(|&m| {
+                let (king_dest, rook_src, _) = self.castle_squares(m, is_white);
+                m.is_castle() && mv.src() == m.src() && mv.promotion().is_none() && (mv.dest() == king_dest || mv.dest() == rook_src)
+            })
+            .ok_or_else(|| Error::IllegalMove(format!("Illegal move {} in {}", uci, self.to_fen())))
+    }
+
+    fn castle_squares(&self, mv: Move, is_white: bool) -> (Square, Square, Square) {
+        let short = mv.dest().file() > mv.src().file();
+        let rank = mv.src().rank();
+        let rook_src = if self.chess960 {
+            mv.dest()
+        } else {
+            Square::new(rank, self.castle_state(is_white).rook_file(short))
+        };
+        (
+            Square::new(rank, if short { 6 } else { 2 }),
+            rook_src,
+            Square::new(rank, if short { 5 } else { 3 })
+        )
+    }
+
     pub fn remove_piece(&mut self, square: Square) {
-        self.white_pieces.set(square, false);
-        self.black_pieces.set(square, false);
-        self.pawns.set(square, false);
-        self.knights.set(square, false);
-        self.bishops.set(square, false);
-        self.rooks.set(square, false);
-        self.queens.set(square, false);
-        self.kings.set(square, false);
+        if let Some(p) = self.board[square.index()].take() {
+            self.by_type_mut(p.p_type).set(square, false);
+            self.by_color_mut(p.is_white).set(square, false);
+            self.hash ^= piece_key(square, p);
+        }
     }
 
-    pub fn by_type_mut(&mut self, p_type: PieceType) -> &mut Bitboard {
+    /// Moves the piece on `src` to the empty square `dest`.
+    fn move_piece(&mut self, src: Square, dest: Square) {
+        debug_assert!(self.board[dest.index()].is_none());
+        if let Some(p) = self.board[src.index()].take() {
+            let squares = Bitboard::from_square(src) | Bitboard::from_square(dest);
+            *self.by_type_mut(p.p_type) ^= squares;
+            *self.by_color_mut(p.is_white) ^= squares;
+            self.board[dest.index()] = Some(p);
+            self.hash ^= piece_key(src, p) ^ piece_key(dest, p);
+        }
+    }
+
+    pub fn by_type(&self, p_type: PieceType) -> Bitboard {
+        match p_type {
+            PieceType::Pawn => self.pawns,
+            PieceType::Knight => self.knights,
+            PieceType::Bishop => self.bishops,
+            PieceType::Rook => self.rooks,
+            PieceType::Queen => self.queens,
+            PieceType::King => self.kings
+        }
+    }
+
+    fn by_type_mut(&mut self, p_type: PieceType) -> &mut Bitboard {
         match p_type {
             PieceType::Pawn => &mut self.pawns,
             PieceType::Knight => &mut self.knights,
@@ -95,7 +500,7 @@ impl Position {
         }
     }
 
-    pub fn by_color_mut(&mut self, white: bool) -> &mut Bitboard {
+    fn by_color_mut(&mut self, white: bool) -> &mut Bitboard {
         if white {
             &mut self.white_pieces
         } else {
@@ -107,28 +512,16 @@ impl Position {
         self.white_pieces.union(self.black_pieces)
     }
 
+    pub fn piece_on(&self, square: Square) -> Option<Piece> {
+        self.board[square.index()]
+    }
+
     pub fn get_piece_type(&self, square: Square) -> Option<PieceType> {
-        if !self.all_pieces().get(square) {
-            return None;
-        }
-        if self.pawns.get(square) { return Some(PieceType::Pawn); }
-        if self.knights.get(square) { return Some(PieceType::Knight); }
-        if self.bishops.get(square) { return Some(PieceType::Bishop); }
-        if self.rooks.get(square) { return Some(PieceType::Rook); }
-        if self.queens.get(square) { return Some(PieceType::Queen); }
-        if self.kings.get(square) { return Some(PieceType::King); }
-        return None;
+        self.piece_on(square).map(|p| p.p_type)
     }
 
     pub fn get_peice(&self, square: Square) -> Option<Piece> {
-        if let Some(p_type) = self.get_piece_type(square) {
-            Some(Piece {
-                is_white: self.white_pieces.get(square),
-                p_type
-            })
-        } else {
-            None
-        }
+        self.piece_on(square)
     }
 
     pub fn set_or_remove_piece(&mut self, square: Square, piece: Option<Piece>) {
@@ -136,6 +529,8 @@ impl Position {
         if let Some(p) = piece {
             self.by_type_mut(p.p_type).set(square, true);
             self.by_color_mut(p.is_white).set(square, true);
+            self.board[square.index()] = Some(p);
+            self.hash ^= piece_key(square, p);
         }
     }
 
@@ -143,152 +538,315 @@ impl Position {
         self.set_or_remove_piece(square, Some(piece));
     }
 
+    /// Passes the turn, as used to ask whether the side that just moved is
+    /// in check. En passant and castling rights are left alone.
     pub fn do_null_move(&mut self) {
+        self.hash ^= self.state_key();
         self.white_to_play = !self.white_to_play;
+        self.hash ^= self.state_key();
+        self.checkers = self.find_checkers();
     }
 
     pub fn undo_null_move(&mut self) {
-        self.white_to_play = !self.white_to_play;
+        self.do_null_move();
     }
 
-    pub fn do_move(&mut self, mv: Move) -> Result<PastMove, String> {
-        if let Some(mut peice) = self.get_peice(mv.src) {
-            let mut captured = self.get_peice(mv.dest);
-            self.remove_piece(mv.src);
+    pub fn do_move(&mut self, mv: Move) -> Result<(), Error> {
+        let Some(mut peice) = self.piece_on(mv.src()) else {
+            return Err(Error::IllegalMove(format!("Source square {} is empty", mv.src())));
+        };
+        let captured = if mv.is_castle() {
+            None
+        } else if mv.is_en_passant() {
+            self.en_passant_target.and_then(|target| self.piece_on(target))
+        } else {
+            self.piece_on(mv.dest())
+        };
+        self.states.push(State {
+            mv,
+            captured,
+            white_castle_state: self.white_castle_state,
+            black_castle_state: self.black_castle_state,
+            en_passant_target: self.en_passant_target,
+            halfmove_clock: self.halfmove_clock,
+            hash: self.hash,
+            checkers: self.checkers
+        });
+        // The castling and en passant keys depend on the pieces too, so they
+        // are taken out before anything moves and put back at the end
+        self.hash ^= self.state_key();
 
-            // Promotion
-            if let Some(promoted_type) = mv.promotion {
+        if mv.is_castle() {
+            self.do_castle(mv, peice.is_white);
+        } else {
+            self.remove_piece(mv.dest());
+            if let Some(promoted_type) = mv.promotion() {
                 peice.p_type = promoted_type;
+                self.remove_piece(mv.src());
+                self.set_piece(mv.dest(), peice);
+            } else {
+                self.move_piece(mv.src(), mv.dest());
+            }
+            if mv.is_en_passant() && let Some(target) = self.en_passant_target {
+                self.remove_piece(target);
             }
 
-            self.set_piece(mv.dest, peice);
-
-            // En passant
-            if peice.p_type == PieceType::Pawn && let Some(target) = self.en_passant_target {
-                if mv.src.rank == target.rank && mv.dest.file == target.file {
-                    captured = self.get_peice(target);
-                    self.remove_piece(target);
-                }
+            if peice.p_type == PieceType::Pawn || captured.is_some() {
+                self.halfmove_clock = 0;
+            } else {
+                self.halfmove_clock += 1;
             }
-            
-            // All information to create past move has been computed
-            let result = PastMove {
-                mv,
-                captured_peice: captured,
-                en_passant_target: self.en_passant_target
-            };
 
             // En passant setup
-            if peice.p_type == PieceType::Pawn && (mv.src.rank - mv.dest.rank).abs() == 2 {
-                self.en_passant_target = Some(mv.dest);
+            if mv.is_double_push() {
+                self.en_passant_target = Some(mv.dest());
             } else {
                 self.en_passant_target = None;
             }
 
-            // Castling
-            let castle_state = if peice.is_white {
-                &mut self.white_castle_state
-            } else {
-                &mut self.black_castle_state
-            };
+            // Castling rights are lost when the king or a castling rook moves,
+            // or the rook is captured
+            let home_rank = if peice.is_white { 0 } else { 7 };
+            let castle_state = self.castle_state_mut(peice.is_white);
             if peice.p_type == PieceType::King {
                 castle_state.can_short_castle = false;
                 castle_state.can_long_castle = false;
-            } else if peice.p_type == PieceType::Rook {
-                if mv.src.file == 0 {
+            } else if peice.p_type == PieceType::Rook && mv.promotion().is_none() && mv.src().rank() == home_rank {
+                if mv.src().file() == castle_state.long_rook_file {
                     castle_state.can_long_castle = false;
-                } else {
+                } else if mv.src().file() == castle_state.short_rook_file {
                     castle_state.can_short_castle = false;
                 }
             }
-            if peice.p_type == PieceType::King && (mv.src.file - mv.dest.file).abs() == 2 {
-                if mv.dest.file > mv.src.file {
-                    // Short castle
-                    self.remove_piece(Square { rank: mv.src.rank, file: 7 });
-                    self.set_piece(
-                        Square { rank: mv.src.rank, file: 5 },
-                        Piece { is_white: peice.is_white, p_type: PieceType::Rook }
-                    );
-                } else {
-                    // Long castle
-                    self.remove_piece(Square { rank: mv.src.rank, file: 0 });
-                    self.set_piece(
-                        Square { rank: mv.src.rank, file: 3 },
-                        Piece { is_white: peice.is_white, p_type: PieceType::Rook }
-                    );
+            if captured.is_some_and(|p| p.p_type == PieceType::Rook) && mv.dest().rank() == 7 - home_rank {
+                let their_state = self.castle_state_mut(!peice.is_white);
+                if mv.dest().file() == their_state.long_rook_file {
+                    their_state.can_long_castle = false;
+                } else if mv.dest().file() == their_state.short_rook_file {
+                    their_state.can_short_castle = false;
                 }
             }
+        }
 
-            // Advance to next turn
-            self.white_to_play = !self.white_to_play;
-            Ok(result)
+        // Advance to next turn
+        if !peice.is_white {
+            self.fullmove_number += 1;
+        }
+        self.white_to_play = !self.white_to_play;
+        self.hash ^= self.state_key();
+        self.checkers = self.find_checkers();
+        #[cfg(debug_assertions)]
+        self.assert_valid(mv);
+        debug_assert_eq!(self.hash, self.compute_hash());
+        Ok(())
+    }
+
+    fn do_castle(&mut self, mv: Move, is_white: bool) {
+        let (king_dest, rook_src, rook_dest) = self.castle_squares(mv, is_white);
+        // In Chess960 the king or rook may land where the other started
+        self.remove_piece(rook_src);
+        if king_dest != mv.src() {
+            self.move_piece(mv.src(), king_dest);
+        }
+        self.set_piece(rook_dest, Piece { is_white, p_type: PieceType::Rook });
+
+        self.halfmove_clock += 1;
+        self.en_passant_target = None;
+        let castle_state = self.castle_state_mut(is_white);
+        castle_state.can_short_castle = false;
+        castle_state.can_long_castle = false;
+    }
+
+    /// Takes back the last move played with `do_move`, restoring the
+    /// position exactly as it was.
+    pub fn undo_move(&mut self) -> Result<(), Error> {
+        let Some(state) = self.states.pop() else {
+            return Err(Error::IllegalMove("No move to undo".to_string()));
+        };
+        let mv = state.mv;
+        let is_white = !self.white_to_play;
+        if mv.is_castle() {
+            let (king_dest, rook_src, rook_dest) = self.castle_squares(mv, is_white);
+            self.remove_piece(rook_dest);
+            if king_dest != mv.src() {
+                self.move_piece(king_dest, mv.src());
+            }
+            self.set_piece(rook_src, Piece { is_white, p_type: PieceType::Rook });
         } else {
-            Err(format!("Source square {} is empty", mv.src))
+            if mv.promotion().is_some() {
+                self.remove_piece(mv.dest());
+                self.set_piece(mv.src(), Piece { is_white, p_type: PieceType::Pawn });
+            } else {
+                self.move_piece(mv.dest(), mv.src());
+            }
+            if let Some(captured) = state.captured {
+                let square = match state.en_passant_target {
+                    Some(target) if mv.is_en_passant() => target,
+                    _ => mv.dest()
+                };
+                self.set_piece(square, captured);
+            }
+        }
+
+        self.white_castle_state = state.white_castle_state;
+        self.black_castle_state = state.black_castle_state;
+        self.en_passant_target = state.en_passant_target;
+        self.halfmove_clock = state.halfmove_clock;
+        self.hash = state.hash;
+        self.checkers = state.checkers;
+        if !is_white {
+            self.fullmove_number -= 1;
+        }
+        self.white_to_play = is_white;
+        #[cfg(debug_assertions)]
+        self.assert_valid(mv);
+        Ok(())
+    }
+
+    /// Checks in debug builds that the position is still sound after `mv` was
+    /// played or taken back. The search plays pseudo-legal moves, which may
+    /// leave the mover in check and then lose its king, so those two errors
+    /// are let through.
+    #[cfg(debug_assertions)]
+    fn assert_valid(&self, mv: Move) {
+        match self.validate() {
+            Ok(()) | Err(PositionError::OpponentInCheck | PositionError::MissingKing { .. }) => {},
+            Err(e) => panic!("Invalid position around {} in {}: {}", mv, self.to_fen(), e)
+        }
+    }
+
+    /// Checks that the piece bitboards agree with each other and with the
+    /// board array, and that no pawn stands on a back rank.
+    fn validate_board(&self) -> Result<(), PositionError> {
+        let colors = self.white_pieces.union(self.black_pieces);
+        let mut overlap = self.white_pieces.intersect(self.black_pieces);
+        let mut pieces = Bitboard::empty();
+        for bitboard in [self.pawns, self.knights, self.bishops, self.rooks, self.queens, self.kings] {
+            overlap = overlap.union(pieces.intersect(bitboard));
+            pieces = pieces.union(bitboard);
+        }
+        // Squares with a color but no piece type or the other way around
+        let unmatched = pieces.union(colors).intersect(pieces.intersect(colors).invert());
+        if let Some(square) = overlap.union(unmatched).into_iter().next() {
+            return Err(PositionError::OverlappingPieces(square));
+        }
+        let on_bitboards = |square: Square, p: Piece| self.by_type(p.p_type).intersect(self.by_color(p.is_white)).get(square);
+        let out_of_step = Square::all().find(|&square| match self.piece_on(square) {
+            Some(p) => !on_bitboards(square, p),
+            None => colors.get(square)
+        });
+        if let Some(square) = out_of_step {
+            return Err(PositionError::OverlappingPieces(square));
+        }
+        let back_ranks = Bitboard::rank(0).union(Bitboard::rank(7));
+        if let Some(square) = self.pawns.intersect(back_ranks).into_iter().next() {
+            return Err(PositionError::PawnOnBackRank(square));
         }
+        Ok(())
     }
 
-    pub fn undo_move(&mut self, past_move: PastMove) -> Result<(), String> {
-        if let Some(mut peice) = self.get_peice(past_move.mv.dest) {
-            if past_move.mv.promotion != None {
-                peice.p_type = PieceType::Pawn;
+    /// Checks that the position could arise in a game: one king each, pawns
+    /// off the back ranks, castling rights backed by the king and rooks on
+    /// their home squares, a real en passant pawn and the side that just
+    /// moved not in check.
+    pub fn validate(&self) -> Result<(), PositionError> {
+        self.validate_board()?;
+        for white in [true, false] {
+            match self.kings.intersect(self.by_color(white)).count() {
+                0 => return Err(PositionError::MissingKing { white }),
+                1 => {},
+                _ => return Err(PositionError::TooManyKings { white })
             }
-            self.set_piece(past_move.mv.src, peice);
 
-            let mut captured_square = past_move.mv.dest;
-            if peice.p_type == PieceType::Pawn && let Some(target) = past_move.en_passant_target {
-                if past_move.mv.src.rank == target.rank && past_move.mv.dest.file == target.file {
-                    self.remove_piece(past_move.mv.dest);
-                    captured_square = target;
+            let state = self.castle_state(white);
+            if state.can_short_castle || state.can_long_castle {
+                let rank = if white { 0 } else { 7 };
+                let our_rooks = self.rooks.intersect(self.by_color(white));
+                let Some(king_file) = self.home_king_file(white) else {
+                    return Err(PositionError::ImpossibleCastlingRights { white });
+                };
+                let has_rook = |short: bool| {
+                    let file = state.rook_file(short);
+                    (file > king_file) == short && our_rooks.get(Square::new(rank, file))
+                };
+                if (state.can_short_castle && !has_rook(true)) || (state.can_long_castle && !has_rook(false)) {
+                    return Err(PositionError::ImpossibleCastlingRights { white });
                 }
             }
+        }
 
-            self.en_passant_target = past_move.en_passant_target;
-
-            // Castling
-            if peice.p_type == PieceType::King && (past_move.mv.src.file - past_move.mv.dest.file).abs() == 2 {
-                if past_move.mv.dest.file > past_move.mv.src.file {
-                    // Short castle
-                    self.remove_piece(Square { rank: past_move.mv.src.rank, file: 5 });
-                    self.set_piece(
-                        Square { rank: past_move.mv.src.rank, file: 7 },
-                        Piece { is_white: peice.is_white, p_type: PieceType::Rook }
-                    );
-                } else {
-                    // Long castle
-                    self.remove_piece(Square { rank: past_move.mv.src.rank, file: 3 });
-                    self.set_piece(
-                        Square { rank: past_move.mv.src.rank, file: 0 },
-                        Piece { is_white: peice.is_white, p_type: PieceType::Rook }
-                    );
-                }
+        // The pawn that just moved two squares, with both squares it passed empty
+        if let Some(target) = self.en_passant_target {
+            let mover = !self.white_to_play;
+            let back = if mover { -1 } else { 1 };
+            let is_pawn = self.pawns.intersect(self.by_color(mover)).get(target);
+            let passed = [back, 2 * back].map(|ranks| target.offset(ranks, 0));
+            let passed_empty = passed.iter().all(|sq| sq.is_some_and(|sq| !self.all_pieces().get(sq)));
+            if target.rank() != if mover { 3 } else { 4 } || !is_pawn || !passed_empty {
+                return Err(PositionError::InvalidEnPassant(target));
             }
+        }
 
-            self.set_or_remove_piece(captured_square, past_move.captured_peice);
-            self.white_to_play = !self.white_to_play;
-            Ok(())
-        } else {
-            Err(format!("Destination square {} is empty", past_move.mv.dest))
+        let their_king = self.kings.intersect(self.by_color(!self.white_to_play)).into_iter().next();
+        if their_king.is_some_and(|king| is_attacked(self, king, self.white_to_play)) {
+            return Err(PositionError::OpponentInCheck);
         }
+        Ok(())
+    }
+
+    /// Whether neither side can possibly mate, with at most one minor piece left.
+    pub fn is_insufficient_material(&self) -> bool {
+        self.pawns.union(self.rooks).union(self.queens).count() == 0
+            && self.knights.union(self.bishops).count() <= 1
     }
 
-    pub fn is_check(&mut self) -> Result<bool, String> {
+    pub fn is_check(&mut self) -> Result<bool, Error> {
         if self.kings.intersect(self.by_color(self.white_to_play)).count() == 0 {
             return Ok(true);
         }
-        let mut moves: Vec<Move> = Vec::new();
-        self.do_null_move();
-        generate_moves(&mut moves, self, true);
-        for mv in moves {
-            let past_move = self.do_move(mv)?;
-            let kings = self.kings.count();
-            self.undo_move(past_move)?;
-            if kings < 2 {
-                self.undo_null_move();
-                return Ok(true);
-            }
+        Ok(!self.checkers.is_empty())
+    }
+
+    /// Pieces giving check to the side to move.
+    pub fn checkers(&self) -> Bitboard {
+        self.checkers
+    }
+
+    fn find_checkers(&self) -> Bitboard {
+        match self.kings.intersect(self.by_color(self.white_to_play)).lsb() {
+            Some(king) => attackers_to(self, king, !self.white_to_play),
+            None => Bitboard::empty()
         }
-        self.undo_null_move();
-        return Ok(false);
+    }
+}
+
+fn piece_key(square: Square, p: Piece) -> u64 {
+    let kind = 2 * p.p_type as usize + p.is_white as usize;
+    RANDOM64[64 * kind + square.index()]
+}
+
+/// Positions are equal when the board and everything the FEN holds match,
+/// however they were reached.
+impl PartialEq for Position {
+    fn eq(&self, other: &Position) -> bool {
+        self.white_to_play == other.white_to_play
+            && self.en_passant_target == other.en_passant_target
+            && self.white_castle_state == other.white_castle_state
+            && self.black_castle_state == other.black_castle_state
+            && self.white_pieces == other.white_pieces
+            && self.black_pieces == other.black_pieces
+            && self.pawns == other.pawns
+            && self.knights == other.knights
+            && self.bishops == other.bishops
+            && self.rooks == other.rooks
+            && self.queens == other.queens
+            && self.kings == other.kings
+            && self.board == other.board
+            && self.halfmove_clock == other.halfmove_clock
+            && self.fullmove_number == other.fullmove_number
+            && self.chess960 == other.chess960
+            && self.hash == other.hash
+            && self.checkers == other.checkers
     }
 }
 
@@ -297,7 +855,7 @@ impl Display for Position {
         for rank in (0..8).rev() {
             write!(f, "{}  ", RANK_NAMES[rank as usize])?;
             for file in 0..8 {
-                let peice = self.get_peice(Square { rank, file });
+                let peice = self.get_peice(Square::new(rank, file));
                 if let Some(p) = peice {
                     write!(f, " {}", p)?;
                 } else {
@@ -308,8 +866,8 @@ impl Display for Position {
         }
         writeln!(f)?;
         write!(f, "   ")?;
-        for file in 0..8 {
-            write!(f, " {}", FILE_NAMES[file])?;
+        for name in FILE_NAMES {
+            write!(f, " {}", name)?;
         }
         Ok(())
     }
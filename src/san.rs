@@ -0,0 +1,134 @@
+use crate::error::Error;
+use crate::moves::{generate_legal_moves, Move, MoveList};
+use crate::piece::{Piece, PieceType};
+use crate::position::Position;
+use crate::square::{Square, FILE_NAMES, RANK_NAMES};
+
+fn legal_moves(pos: &Position) -> Result<MoveList, Error> {
+    let mut moves = MoveList::new();
+    generate_legal_moves(&mut moves, &mut pos.snapshot())?;
+    Ok(moves)
+}
+
+fn piece_type_from_char(c: char) -> Option<PieceType> {
+    match c {
+        'N' => Some(PieceType::Knight),
+        'B' => Some(PieceType::Bishop),
+        'R' => Some(PieceType::Rook),
+        'Q' => Some(PieceType::Queen),
+        'K' => Some(PieceType::King),
+        _ => None
+    }
+}
+
+impl Position {
+    /// Formats a legal move in standard algebraic notation, such as `Nbd2`,
+    /// `exd6`, `e8=Q+` or `O-O-O#`.
+    pub fn to_san(&self, mv: Move) -> Result<String, Error> {
+        let p_type = self.get_piece_type(mv.src()).ok_or_else(|| Error::IllegalMove(format!("Source square {} is empty", mv.src())))?;
+        let mut san = String::new();
+
+        if mv.is_castle() {
+            san.push_str(if mv.dest().file() > mv.src().file() { "O-O" } else { "O-O-O" });
+        } else {
+            let is_capture = mv.is_capture();
+            if p_type == PieceType::Pawn {
+                if is_capture {
+                    san.push(FILE_NAMES[mv.src().file() as usize]);
+                }
+            } else {
+                san.push_str(&Piece::white(p_type).to_string());
+
+                // Name the file, else the rank, else both if another piece could go there too
+                let others: Vec<Square> = legal_moves(self)?.into_iter()
+                    .filter(|m| m.dest() == mv.dest() && m.src() != mv.src() && self.get_piece_type(m.src()) == Some(p_type))
+                    .map(|m| m.src())
+                    .collect();
+                if !others.is_empty() {
+                    if others.iter().all(|s| s.file() != mv.src().file()) {
+                        san.push(FILE_NAMES[mv.src().file() as usize]);
+                    } else if others.iter().all(|s| s.rank() != mv.src().rank()) {
+                        san.push(RANK_NAMES[mv.src().rank() as usize]);
+                    } else {
+                        san.push_str(&mv.src().to_string());
+                    }
+                }
+            }
+            if is_capture {
+                san.push('x');
+            }
+            san.push_str(&mv.dest().to_string());
+            if let Some(promotion) = mv.promotion() {
+                san.push('=');
+                san.push_str(&Piece::white(promotion).to_string());
+            }
+        }
+
+        let mut after = self.snapshot();
+        after.do_move(mv)?;
+        if after.is_check()? {
+            san.push(if legal_moves(&after)?.is_empty() { '#' } else { '+' });
+        }
+        Ok(san)
+    }
+
+    /// Finds the legal move written in standard algebraic notation. Check
+    /// marks and annotations are optional, castling may use `0` or `O`.
+    pub fn parse_san(&self, san: &str) -> Result<Move, Error> {
+        let text = san.trim_end_matches(['+', '#', '!', '?']);
+        let moves = legal_moves(self)?;
+
+        if matches!(text, "O-O" | "0-0" | "O-O-O" | "0-0-0") {
+            let short = text.len() == 3;
+            return moves.into_iter()
+                .find(|&m| m.is_castle() && (m.dest().file() > m.src().file()) == short)
+                .ok_or_else(|| Error::IllegalMove(format!("Illegal move {}", san)));
+        }
+
+        let mut chars: Vec<char> = text.chars().filter(|&c| c != 'x' && c != '-').collect();
+        let p_type = match chars.first().copied().and_then(piece_type_from_char) {
+            Some(p_type) => {
+                chars.remove(0);
+                p_type
+            }
+            None => PieceType::Pawn
+        };
+
+        let mut promotion = None;
+        if let Some(&last) = chars.last() && let Some(promoted) = piece_type_from_char(last.to_ascii_uppercase())
+            && p_type == PieceType::Pawn {
+            promotion = Some(promoted);
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+        }
+
+        if chars.len() < 2 {
+            return Err(Error::Parse(format!("Invalid move {}", san)));
+        }
+        let dest_file = FILE_NAMES.iter().position(|&c| c == chars[chars.len() - 2]);
+        let dest_rank = RANK_NAMES.iter().position(|&c| c == chars[chars.len() - 1]);
+        let (Some(dest_file), Some(dest_rank)) = (dest_file, dest_rank) else {
+            return Err(Error::Parse(format!("Invalid move {}", san)));
+        };
+        let dest = Square::new(dest_rank as i8, dest_file as i8);
+        let hints = &chars[..chars.len() - 2];
+        let file = hints.iter().find_map(|c| FILE_NAMES.iter().position(|f| f == c));
+        let rank = hints.iter().find_map(|c| RANK_NAMES.iter().position(|r| r == c));
+
+        let candidates: Vec<Move> = moves.into_iter().filter(|m| {
+            m.dest() == dest
+                && m.promotion() == promotion
+                && self.get_piece_type(m.src()) == Some(p_type)
+                && !m.is_castle()
+                && file.is_none_or(|f| m.src().file() as usize == f)
+                && rank.is_none_or(|r| m.src().rank() as usize == r)
+        }).collect();
+        match candidates.as_slice() {
+            [mv] => Ok(*mv),
+            [] => Err(Error::IllegalMove(format!("Illegal move {}", san))),
+            _ => Err(Error::IllegalMove(format!("Ambiguous move {}", san)))
+        }
+    }
+}
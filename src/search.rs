@@ -0,0 +1,448 @@
+use core::f32;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::error::Error;
+use crate::eval::evaluate;
+use crate::moves::{generate_legal_moves, generate_moves, Move, MoveList};
+use crate::params::EvalParams;
+use crate::position::Position;
+use crate::syzygy::{Tablebases, WDL_CURSED_WIN, WDL_BLESSED_LOSS};
+
+/// Score of a position the tablebases say is won, below mate but above any evaluation.
+const TB_WIN: f32 = 1000.0;
+
+/// How long a search runs before it reports each root move, so short
+/// searches do not flood the GUI.
+const CURRMOVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Full strength, no handicap.
+pub const MAX_SKILL_LEVEL: u32 = 20;
+
+/// Range of `UCI_Elo`, mapped linearly onto the skill levels.
+pub const MIN_ELO: u32 = 800;
+pub const MAX_ELO: u32 = 2200;
+
+pub fn skill_level_from_elo(elo: u32) -> u32 {
+    let elo = elo.clamp(MIN_ELO, MAX_ELO);
+    (elo - MIN_ELO) * MAX_SKILL_LEVEL / (MAX_ELO - MIN_ELO)
+}
+
+/// How long to think with `remaining` on the clock and `increment` added
+/// after each move: a fraction of what is left, keeping a little back for
+/// overhead.
+pub fn time_budget(remaining: Duration, increment: Duration) -> Duration {
+    let budget = remaining / 30 + increment / 2;
+    budget.min(remaining.saturating_sub(Duration::from_millis(50))).max(Duration::from_millis(1))
+}
+
+/// Picks among the searched `lines` for a limited skill level: the weaker
+/// the level, the more random noise is added to each line's score, scaled
+/// by how far apart the candidate moves are.
+fn pick_skill_move(lines: &[(f32, MoveChain)], skill_level: u32, rng: &mut impl Rng) -> Option<Move> {
+    let centipawns = |score: f32| (score * 100.0).clamp(-10000.0, 10000.0) as i32;
+    let top = centipawns(lines.first()?.0);
+    let weakness = 120 - 2 * skill_level as i32;
+    let delta = (top - centipawns(lines.last()?.0)).min(100);
+
+    let mut best = None;
+    let mut best_score = i32::MIN;
+    for (score, mv) in lines {
+        let score = centipawns(*score);
+        let push = (weakness * (top - score) + delta * rng.random_range(0..weakness)) / 128;
+        if score + push >= best_score {
+            best_score = score + push;
+            best = Some(mv.current);
+        }
+    }
+    best
+}
+
+pub struct MoveChain {
+    pub current: Move,
+    pub next: Option<Box<MoveChain>>
+}
+
+impl MoveChain {
+    pub fn new(current: Move, next: Option<MoveChain>) -> Self {
+        Self {
+            current,
+            next: next.map(Box::new)
+        }
+    }
+}
+
+impl Display for MoveChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.current)?;
+        if let Some(next) = &self.next {
+            write!(f, " {}", next)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct SearchContext {
+    pub nodes: u32,
+    /// Searching stops once `nodes` reaches this limit
+    pub node_limit: u32,
+    pub params: EvalParams,
+    pub tablebases: Option<Arc<Tablebases>>,
+    /// Tablebases are only probed with at most this many pieces on the board
+    pub tb_probe_limit: usize,
+    /// Whether cursed wins and blessed losses count as draws
+    pub tb_rule50: bool,
+    pub tb_hits: u64,
+    /// Moves `go searchmoves` limits the search to, all legal moves if empty
+    pub search_moves: Vec<Move>,
+    /// Moves the root is restricted to, all legal moves if empty
+    root_moves: Vec<Move>,
+    /// Whether to print `info currmove` as each root move is searched, once
+    /// the search has run for `CURRMOVE_DELAY`
+    pub report_currmove: bool,
+    /// When the current search started
+    start_time: Instant,
+    /// Playing strength from 0 to `MAX_SKILL_LEVEL`, lower levels search
+    /// shallower and sometimes pick a worse move
+    pub skill_level: u32,
+    /// Number of best lines to search and report
+    pub multi_pv: usize,
+    /// Root moves left out because an earlier line already covers them
+    excluded_moves: Vec<Move>
+}
+
+impl SearchContext {
+    pub fn new() -> Self {
+        Self {
+            nodes: 0,
+            node_limit: u32::MAX,
+            params: EvalParams::default(),
+            tablebases: None,
+            tb_probe_limit: 7,
+            tb_rule50: true,
+            tb_hits: 0,
+            search_moves: Vec::new(),
+            root_moves: Vec::new(),
+            report_currmove: false,
+            start_time: Instant::now(),
+            skill_level: MAX_SKILL_LEVEL,
+            multi_pv: 1,
+            excluded_moves: Vec::new()
+        }
+    }
+
+    fn reset(&mut self) {
+        self.nodes = 0;
+    }
+
+    fn should_stop(&self, is_done: &AtomicBool) -> bool {
+        is_done.load(Ordering::Relaxed) || self.nodes >= self.node_limit
+    }
+
+    /// Probes the tablebases right after captures and pawn moves, when
+    /// the position has few enough pieces.
+    fn probe_tablebases(&mut self, pos: &mut Position) -> Option<f32> {
+        let tb = self.tablebases.as_ref()?;
+        if pos.halfmove_clock != 0 || pos.all_pieces().count() as usize > self.tb_probe_limit || !tb.can_probe(pos) {
+            return None;
+        }
+        let wdl = tb.probe_wdl(pos)?;
+        self.tb_hits += 1;
+        let cursed = wdl == WDL_CURSED_WIN || wdl == WDL_BLESSED_LOSS;
+        Some(if cursed && self.tb_rule50 { 0.02 * wdl as f32 } else { TB_WIN * wdl.signum() as f32 })
+    }
+
+    /// Restricts the root to the search moves, and among those to the ones
+    /// keeping the best tablebase result.
+    fn filter_root_moves(&mut self, pos: &mut Position) -> Result<(), Error> {
+        self.root_moves = self.search_moves.clone();
+        if let Some(tb) = &self.tablebases && tb.can_probe(pos) {
+            let mut candidates = self.root_moves.clone();
+            if candidates.is_empty() {
+                let mut legal = MoveList::new();
+                generate_legal_moves(&mut legal, &mut pos.snapshot())?;
+                candidates.extend_from_slice(&legal);
+            }
+            if let Some(moves) = tb.filter_root_moves(pos, &candidates, self.tb_rule50) {
+                self.tb_hits += moves.len() as u64;
+                self.root_moves = moves;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for SearchContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn minimax(ctx: &mut SearchContext, pos: &mut Position, depth: i32, is_root: bool, is_done: &AtomicBool,
+        mut alpha: f32, beta: f32) -> Result<(f32, Option<MoveChain>), Error> {
+    ctx.nodes += 1;
+
+    if depth < 1 {
+        let score = evaluate(pos, &ctx.params);
+        return Ok((score, None))
+    }
+
+    if !is_root && let Some(score) = ctx.probe_tablebases(pos) {
+        return Ok((score, None));
+    }
+
+    let mut moves = MoveList::new();
+    if is_root {
+        generate_legal_moves(&mut moves, pos)?;
+        if !ctx.root_moves.is_empty() {
+            moves.retain(|mv| ctx.root_moves.contains(mv));
+        }
+        moves.retain(|mv| !ctx.excluded_moves.contains(mv));
+    } else {
+        generate_moves(&mut moves, pos, true);
+        generate_moves(&mut moves, pos, false);
+    }
+
+    if moves.is_empty() {
+        if pos.is_check()? {
+            return Ok((f32::NEG_INFINITY, None));
+        } else {
+            return Ok((0f32, None));
+        }
+    }
+
+    let mut best_chain: Option<MoveChain> = None;
+    for (i, mv) in moves.iter().enumerate() {
+        // Moves are numbered within each MultiPV pass
+        if is_root && ctx.report_currmove && ctx.start_time.elapsed() >= CURRMOVE_DELAY {
+            println!("info depth {} currmove {} currmovenumber {}", depth, mv, i + 1);
+        }
+        pos.do_move(*mv)?;
+        let (mut score, chain) = minimax(ctx, pos, depth - 1, false, is_done, -beta, -alpha)?;
+        score *= -1f32;
+        pos.undo_move()?;
+
+        if ctx.should_stop(is_done) {
+            return Ok((f32::NEG_INFINITY, None));
+        }
+
+        if score > alpha {
+            alpha = score;
+            best_chain = Some(MoveChain::new(*mv, chain));
+            if alpha >= beta {
+                break;
+            }
+        }
+    }
+    return Ok((alpha, best_chain));
+}
+
+/// Deepens the search of `pos` one iteration at a time until `stop` is set
+/// or the skill level's depth limit is reached, printing `info` for each
+/// iteration. Returns the move to play.
+fn iterate(ctx: &mut SearchContext, pos: &mut Position, stop: &AtomicBool) -> Result<Option<Move>, Error> {
+    let mut rng = rand::rng();
+    ctx.start_time = Instant::now();
+    ctx.tb_hits = 0;
+
+    // Weaker levels search shallower and fewer nodes, and look at a few
+    // moves to have something to choose from
+    let skill_level = ctx.skill_level;
+    let limited = skill_level < MAX_SKILL_LEVEL;
+    let max_depth = if limited { 1 + skill_level as i32 / 5 } else { i32::MAX };
+    let max_nodes = if limited { 1000u64 << (skill_level / 2) } else { u64::MAX };
+    let multi_pv = if limited { ctx.multi_pv.max(4) } else { ctx.multi_pv };
+    let mut total_nodes = 0u64;
+    ctx.filter_root_moves(pos)?;
+
+    // The first iteration always completes, so there is a move to play
+    // even when the search is stopped right away
+    let first_iteration = AtomicBool::new(false);
+    let mut best = None;
+    for depth in 1..=max_depth {
+        let is_done = if best.is_some() { stop } else { &first_iteration };
+        let start_time = Instant::now();
+        ctx.reset();
+        ctx.excluded_moves.clear();
+
+        // Each further line is the best move among those not yet reported
+        let mut lines = Vec::new();
+        while lines.len() < multi_pv {
+            let (score, best_mv) = minimax(ctx, pos, depth, true, is_done, f32::NEG_INFINITY, f32::INFINITY)?;
+            let Some(mv) = best_mv else { break };
+            if is_done.load(Ordering::Relaxed) {
+                break;
+            }
+            ctx.excluded_moves.push(mv.current);
+            lines.push((score, mv));
+        }
+        ctx.excluded_moves.clear();
+
+        if is_done.load(Ordering::Relaxed) || lines.is_empty() {
+            break;
+        }
+
+        let minimax_time = start_time.elapsed();
+        let time_ms =  minimax_time.as_millis();
+        let nps = (ctx.nodes as f32 / minimax_time.as_secs_f32()) as u32;
+
+        best = if limited {
+            pick_skill_move(&lines, skill_level, &mut rng)
+        } else {
+            lines.first().map(|(_, mv)| mv.current)
+        };
+        // Extra lines searched for a limited skill level are not reported
+        for (i, (score, mv)) in lines.iter().take(ctx.multi_pv).enumerate() {
+            println!("info depth {} multipv {} time {} nodes {} nps {} tbhits {} score cp {} pv {}",
+                depth,
+                i + 1,
+                time_ms,
+                ctx.nodes,
+                nps,
+                ctx.tb_hits,
+                (score * 100f32) as i32,
+                mv
+            );
+        }
+
+        // The node limit is checked between iterations, so the last one may go past it
+        total_nodes += ctx.nodes as u64;
+        if total_nodes >= max_nodes {
+            break;
+        }
+    }
+    Ok(best)
+}
+
+/// A search running on its own thread, started by `iterative_deepening`.
+pub struct SearchThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>
+}
+
+impl SearchThread {
+    /// Runs `search` on a new thread, handing it the flag `stop` sets.
+    pub(crate) fn spawn(search: impl FnOnce(&Arc<AtomicBool>) + Send + 'static) -> SearchThread {
+        let stop = Arc::new(AtomicBool::new(false));
+        let search_stop = stop.clone();
+        let handle = thread::spawn(move || search(&search_stop));
+        SearchThread { stop, handle }
+    }
+
+    /// Ends the search as soon as it has a move, and waits for its `bestmove`.
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if self.handle.join().is_err() {
+            println!("info string The search thread panicked");
+        }
+    }
+}
+
+/// Starts searching `pos` on a new thread for up to `max_time`, printing
+/// `info` after each iteration and `bestmove` at the end, and returns right
+/// away so the caller can keep reading commands.
+pub fn iterative_deepening(ctx: Arc<Mutex<SearchContext>>, mut pos: Position, max_time: Duration) -> SearchThread {
+    SearchThread::spawn(move |stop| search_and_report(&ctx, &mut pos, stop, max_time))
+}
+
+/// Searches `pos` until `stop` is set or `max_time` has passed, and prints the `bestmove`.
+pub(crate) fn search_and_report(ctx: &Mutex<SearchContext>, pos: &mut Position, stop: &Arc<AtomicBool>, max_time: Duration) {
+    let (tx, rx) = mpsc::channel::<()>();
+    let timer_stop = stop.clone();
+    thread::spawn(move || {
+        // Dropping `tx` once the search is over wakes this up early
+        if rx.recv_timeout(max_time) == Err(RecvTimeoutError::Timeout) {
+            timer_stop.store(true, Ordering::Relaxed);
+        }
+    });
+
+    // A panic in an earlier search leaves the context usable
+    let mut ctx = ctx.lock().unwrap_or_else(PoisonError::into_inner);
+    match iterate(&mut ctx, pos, stop) {
+        Ok(Some(mv)) => println!("bestmove {}", mv),
+        // No legal moves
+        Ok(None) => println!("bestmove 0000"),
+        Err(e) => println!("info string {}", e)
+    }
+    drop(tx);
+}
+
+
+/// Limits for `search`, which stops at whichever is reached first. With none
+/// set it only stops on a forced mate.
+#[derive(Clone, Default)]
+pub struct SearchLimits {
+    pub depth: Option<i32>,
+    /// Total nodes over all iterations
+    pub nodes: Option<u32>,
+    pub time: Option<Duration>,
+    /// Moves the root is restricted to, all legal moves if empty
+    pub search_moves: Vec<Move>
+}
+
+/// Outcome of the deepest iteration `search` completed.
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    /// Score in pawns for the side to move, infinite for a forced mate
+    pub score: f32,
+    pub pv: Option<MoveChain>,
+    pub depth: i32,
+    /// Nodes of all iterations, including the unfinished last one
+    pub nodes: u64,
+    pub elapsed: Duration
+}
+
+/// Searches `pos` with iterative deepening within `limits`, without printing
+/// anything. The position is left as it was.
+pub fn search(ctx: &mut SearchContext, pos: &mut Position, limits: &SearchLimits) -> Result<SearchResult, Error> {
+    let start_time = Instant::now();
+    let is_done = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel::<()>();
+    if let Some(max_time) = limits.time {
+        let timer_is_done = is_done.clone();
+        thread::spawn(move || {
+            // Dropping `tx` once the search is over wakes this up early
+            if rx.recv_timeout(max_time) == Err(RecvTimeoutError::Timeout) {
+                timer_is_done.store(true, Ordering::Relaxed);
+            }
+        });
+    }
+
+    ctx.reset();
+    ctx.start_time = start_time;
+    ctx.tb_hits = 0;
+    ctx.node_limit = limits.nodes.unwrap_or(u32::MAX);
+    ctx.search_moves = limits.search_moves.clone();
+    ctx.filter_root_moves(pos)?;
+    let mut result = SearchResult {
+        best_move: None,
+        score: 0f32,
+        pv: None,
+        depth: 0,
+        nodes: 0,
+        elapsed: Duration::ZERO
+    };
+    for depth in 1..=limits.depth.unwrap_or(i32::MAX) {
+        let (score, pv) = minimax(ctx, pos, depth, true, &is_done, f32::NEG_INFINITY, f32::INFINITY)?;
+        if ctx.should_stop(&is_done) {
+            break;
+        }
+        result.best_move = pv.as_ref().map(|pv| pv.current);
+        (result.score, result.pv, result.depth) = (score, pv, depth);
+        // Deeper iterations cannot change a mate or a position without moves
+        if score.is_infinite() || result.pv.is_none() {
+            break;
+        }
+    }
+    drop(tx);
+    ctx.node_limit = u32::MAX;
+    result.nodes = ctx.nodes as u64;
+    result.elapsed = start_time.elapsed();
+    Ok(result)
+}
@@ -1,42 +1,81 @@
 use std::{fmt::Display, str::FromStr};
 
+use crate::error::Error;
+
 pub const RANK_NAMES: [char; 8] = ['1', '2', '3', '4', '5', '6', '7', '8'];
 pub const FILE_NAMES: [char; 8] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h'];
 
-#[derive(Clone, Copy, PartialEq)]
-pub struct Square {
-    pub rank: i8,
-    pub file: i8
-}
+/// A square on the board, numbered from 0 for a1 to 63 for h8 going rank by
+/// rank, so b1 is 1 and a2 is 8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Square(u8);
 
 impl Square {
-    pub fn is_valid(&self) -> bool {
-        self.file >= 0 && self.rank >= 0 && self.rank < 8 && self.file < 8
+    /// The square on `rank` and `file`, both from 0 to 7.
+    pub const fn new(rank: i8, file: i8) -> Square {
+        debug_assert!(rank >= 0 && rank < 8 && file >= 0 && file < 8);
+        Square((rank * 8 + file) as u8)
     }
 
-    pub fn add(&self, ranks: i8, files: i8) -> Square {
-        Square {
-            rank: self.rank + ranks,
-            file: self.file + files
+    /// The square on `rank` and `file`, or `None` if that is off the board.
+    pub const fn try_new(rank: i8, file: i8) -> Option<Square> {
+        if rank >= 0 && rank < 8 && file >= 0 && file < 8 {
+            Some(Square::new(rank, file))
+        } else {
+            None
         }
     }
+
+    pub const fn from_index(index: usize) -> Square {
+        debug_assert!(index < 64);
+        Square(index as u8)
+    }
+
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub const fn rank(self) -> i8 {
+        (self.0 / 8) as i8
+    }
+
+    pub const fn file(self) -> i8 {
+        (self.0 % 8) as i8
+    }
+
+    /// The square `ranks` up and `files` to the right, if still on the board.
+    pub const fn offset(self, ranks: i8, files: i8) -> Option<Square> {
+        Square::try_new(self.rank() + ranks, self.file() + files)
+    }
+
+    /// The same square seen from the other side's point of view.
+    pub const fn flip_rank(self) -> Square {
+        Square(self.0 ^ 56)
+    }
+
+    pub fn all() -> impl Iterator<Item = Square> {
+        (0..64).map(Square)
+    }
 }
 
 impl FromStr for Square {
-    type Err = String;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let file_char = s.chars().nth(0).unwrap();
-        let rank_char = s.chars().nth(1).unwrap();
-        Ok(Square {
-            rank: RANK_NAMES.iter().position(|&c| c == rank_char).unwrap() as i8,
-            file: FILE_NAMES.iter().position(|&c| c == file_char).unwrap() as i8
-        })
+        let invalid = || Error::Parse(format!("Invalid square '{}'", s));
+        let mut chars = s.chars();
+        let (Some(file_char), Some(rank_char), None) = (chars.next(), chars.next(), chars.next()) else {
+            return Err(invalid());
+        };
+        Ok(Square::new(
+            RANK_NAMES.iter().position(|&c| c == rank_char).ok_or_else(invalid)? as i8,
+            FILE_NAMES.iter().position(|&c| c == file_char).ok_or_else(invalid)? as i8
+        ))
     }
 }
 
 impl Display for Square {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}", FILE_NAMES[self.file as usize], RANK_NAMES[self.rank as usize])
+        write!(f, "{}{}", FILE_NAMES[self.file() as usize], RANK_NAMES[self.rank() as usize])
     }
-}
\ No newline at end of file
+}
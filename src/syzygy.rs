@@ -0,0 +1,1076 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::endgame::{material_key, signature_key};
+use crate::error::Error;
+use crate::moves::{generate_legal_moves, Move, MoveList};
+use crate::piece::PieceType;
+use crate::position::Position;
+use crate::square::Square;
+
+// Syzygy tablebase probing. The `.rtbw` files store win/draw/loss for every
+// position of a material signature and the `.rtbz` files the distance to the
+// next capture or pawn move (DTZ). Tables are read into memory the first time
+// they are probed.
+//
+// Squares here are plain indices `rank * 8 + file` and pieces use the file
+// format's codes: 1 (pawn) to 6 (king), plus 8 for black.
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+const MAX_PIECES: usize = 7;
+
+// Flags of a single table inside a file
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/// Loss, blessed loss (drawn by the fifty-move rule), draw, cursed win and win.
+pub const WDL_LOSS: i32 = -2;
+pub const WDL_BLESSED_LOSS: i32 = -1;
+pub const WDL_DRAW: i32 = 0;
+pub const WDL_CURSED_WIN: i32 = 1;
+pub const WDL_WIN: i32 = 2;
+
+#[derive(Clone, Copy, PartialEq)]
+enum TableKind {
+    Wdl,
+    Dtz
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ProbeState {
+    Ok,
+    /// The DTZ table only stores the other side to move
+    ChangeStm,
+    /// The best move is a capture or pawn move, so the DTZ table cannot be trusted
+    ZeroingBestMove
+}
+
+/// Index tables shared by every probe, built once.
+struct Encoding {
+    map_pawns: [usize; 64],
+    map_b1h1h7: [usize; 64],
+    map_a1d1d4: [usize; 64],
+    map_kk: [[usize; 64]; 10],
+    binomial: [[u64; 64]; 6],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6]
+}
+
+/// Distance from the a1-h8 diagonal, negative below it.
+fn off_a1h8(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+fn encoding() -> &'static Encoding {
+    static ENCODING: OnceLock<Encoding> = OnceLock::new();
+    ENCODING.get_or_init(|| {
+        let mut e = Encoding {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 6],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6]
+        };
+
+        // Squares below the diagonal to 0..27
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_a1h8(sq) < 0 {
+                e.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        // The a1-d1-d4 triangle to 0..9, with the diagonal squares last
+        let mut diagonal = Vec::new();
+        code = 0;
+        for sq in [0, 1, 2, 3, 9, 10, 11, 18, 19, 27] {
+            if off_a1h8(sq) < 0 {
+                e.map_a1d1d4[sq] = code;
+                code += 1;
+            } else {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            e.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // The 462 legal placements of two kings with the first in the triangle
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                if e.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    let distance = (s1 / 8).abs_diff(s2 / 8).max((s1 % 8).abs_diff(s2 % 8));
+                    if distance <= 1 || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+                        continue;
+                    }
+                    if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        e.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            e.map_kk[idx][s2] = code;
+            code += 1;
+        }
+        debug_assert_eq!(code, 462);
+
+        e.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                e.binomial[k][n] = if k > 0 { e.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { e.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // Pawn squares a2-h7 to 0..47, the leading pawn is the one with the
+        // highest value: nearest the edge and lowest on its file
+        for file in 0..4 {
+            for rank in 1..7 {
+                let available = 47 - 2 * (file * 6 + rank - 1);
+                e.map_pawns[rank * 8 + file] = available;
+                e.map_pawns[(rank * 8 + file) ^ 7] = available - 1;
+            }
+        }
+        for lead_pawns in 1..6 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    e.lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += e.binomial[lead_pawns - 1][e.map_pawns[sq]];
+                }
+                e.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        e
+    })
+}
+
+/// Decoding information of one table inside a file. There is one per side to
+/// move and, for tables with pawns, per file of the leading pawn.
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    min_sym_len: usize,
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    block_length_size: usize,
+    sparse_index_size: usize,
+    // Offsets into the file
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    sparse_index: usize,
+    data: usize,
+    base64: Vec<u64>,
+    /// Number of values minus one each symbol expands to
+    symlen: Vec<u8>,
+    pieces: [u8; MAX_PIECES],
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    map_idx: [usize; 4]
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+impl PairsData {
+    fn btree_left(&self, bytes: &[u8], sym: usize) -> usize {
+        let lr = &bytes[self.btree + 3 * sym..];
+        ((lr[1] as usize & 0xF) << 8) | lr[0] as usize
+    }
+
+    fn btree_right(&self, bytes: &[u8], sym: usize) -> usize {
+        let lr = &bytes[self.btree + 3 * sym..];
+        ((lr[2] as usize) << 4) | (lr[1] as usize >> 4)
+    }
+
+    /// Computes how many values each symbol of the recursive pairing expands
+    /// to, or `None` if a pair refers to a symbol past the end of the table.
+    fn set_symlen(&mut self, bytes: &[u8], sym: usize, visited: &mut [bool]) -> Option<u8> {
+        visited[sym] = true;
+        let right = self.btree_right(bytes, sym);
+        if right == 0xFFF {
+            return Some(0);
+        }
+        let left = self.btree_left(bytes, sym);
+        if left >= self.symlen.len() || right >= self.symlen.len() {
+            return None;
+        }
+        for child in [left, right] {
+            if !visited[child] {
+                self.symlen[child] = self.set_symlen(bytes, child, visited)?;
+            }
+        }
+        Some(self.symlen[left].wrapping_add(self.symlen[right]).wrapping_add(1))
+    }
+
+    /// Reads the Huffman code description starting at `data`, returning the offset after it.
+    fn set_sizes(&mut self, bytes: &[u8], mut data: usize) -> Option<usize> {
+        self.flags = *bytes.get(data)?;
+        data += 1;
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            // The only value is stored in place of the minimum symbol length
+            self.min_sym_len = *bytes.get(data)? as usize;
+            return Some(data + 1);
+        }
+
+        let groups = self.group_len.iter().position(|&len| len == 0)?;
+        let tb_size = self.group_idx[groups];
+
+        self.block_size = 1 << bytes.get(data)?;
+        self.span = 1 << bytes.get(data + 1)?;
+        self.sparse_index_size = tb_size.div_ceil(self.span) as usize;
+        let padding = *bytes.get(data + 2)? as usize;
+        self.num_blocks = read_u32(bytes, data + 3)? as usize;
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = *bytes.get(data + 7)? as usize;
+        self.min_sym_len = *bytes.get(data + 8)? as usize;
+        data += 9;
+        self.lowest_sym = data;
+        if max_sym_len < self.min_sym_len || self.min_sym_len == 0 {
+            return None;
+        }
+
+        // Canonical Huffman: longer codes have lower values, base64[i] is the
+        // lowest code of length min_sym_len + i padded to 64 bits
+        let lengths = max_sym_len - self.min_sym_len + 1;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let lowest = read_u16(bytes, self.lowest_sym + 2 * i)? as u64;
+            let next_lowest = read_u16(bytes, self.lowest_sym + 2 * (i + 1))? as u64;
+            self.base64[i] = (self.base64[i + 1] + lowest).wrapping_sub(next_lowest) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base.checked_shl((64 - i - self.min_sym_len) as u32).unwrap_or(0);
+        }
+        data += lengths * 2;
+
+        let symbols = read_u16(bytes, data)? as usize;
+        data += 2;
+        self.btree = data;
+        bytes.get(self.btree..self.btree + 3 * symbols)?;
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.set_symlen(bytes, sym, &mut visited)?;
+            }
+        }
+        Some(data + 3 * symbols + (symbols & 1))
+    }
+
+    /// Finds the value stored at `idx`, failing if the sparse index points
+    /// outside the table's blocks.
+    fn decompress(&self, bytes: &[u8], idx: u64) -> Result<i32, Error> {
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            return Ok(self.min_sym_len as i32);
+        }
+        let corrupt = || Error::Parse(format!("Tablebase index {} points outside the table", idx));
+
+        // The sparse index points near the block holding idx, walk from there
+        let k = (idx / self.span) as usize;
+        let entry = self.sparse_index + 6 * k;
+        let mut block = read_u32(bytes, entry).unwrap_or(0) as usize;
+        let mut offset = read_u16(bytes, entry + 4).unwrap_or(0) as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        let block_length = |block: usize| read_u16(bytes, self.block_length + 2 * block).unwrap_or(0) as i64;
+        while offset < 0 {
+            block = block.checked_sub(1).ok_or_else(corrupt)?;
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+            if block >= self.num_blocks {
+                return Err(corrupt());
+            }
+        }
+
+        // Read symbols until the one covering our offset
+        let mut ptr = self.data + block * self.block_size;
+        let read_be = |ptr: usize| bytes.get(ptr..ptr + 4).map_or(0, |b| u32::from_be_bytes(b.try_into().unwrap())) as u64;
+        let mut buf64 = (read_be(ptr) << 32) | read_be(ptr + 4);
+        ptr += 8;
+        let mut buf64_size = 64;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while len + 1 < self.base64.len() && buf64 < self.base64[len] {
+                len += 1;
+            }
+            sym = ((buf64 - self.base64[len]) >> (64 - len - self.min_sym_len)) as usize;
+            sym += read_u16(bytes, self.lowest_sym + 2 * len).unwrap_or(0) as usize;
+            let count = *self.symlen.get(sym).ok_or_else(corrupt)? as i64 + 1;
+            if offset < count {
+                break;
+            }
+            offset -= count;
+            let consumed = len + self.min_sym_len;
+            buf64 = buf64.checked_shl(consumed as u32).unwrap_or(0);
+            buf64_size -= consumed as i32;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= read_be(ptr) << (64 - buf64_size);
+                ptr += 4;
+            }
+        }
+
+        // Expand the pairs down to the single value we need
+        while self.symlen[sym] != 0 {
+            let left = self.btree_left(bytes, sym);
+            let count = self.symlen[left] as i64 + 1;
+            if offset < count {
+                sym = left;
+            } else {
+                offset -= count;
+                sym = self.btree_right(bytes, sym);
+            }
+        }
+        Ok(self.btree_left(bytes, sym) as i32)
+    }
+}
+
+/// What is known about a tablebase before its file is read.
+struct TableInfo {
+    /// Material key with the first side of the name as white
+    key: u64,
+    /// Material key with the colors swapped
+    key2: u64,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    /// Pawns of the leading color and of the other color
+    pawn_count: [usize; 2]
+}
+
+impl TableInfo {
+    /// Parses a name such as `KRPvKR`.
+    fn from_name(name: &str) -> Option<TableInfo> {
+        let (white, black) = name.split_once('v')?;
+        if !white.starts_with('K') || !black.starts_with('K')
+            || !name.chars().all(|c| "KQRBNPv".contains(c)) {
+            return None;
+        }
+        let count = |side: &str, c: char| side.chars().filter(|&p| p == c).count();
+        let has_unique_pieces = "QRBNP".chars().any(|c| count(white, c) == 1 || count(black, c) == 1);
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        // The side with fewer pawns leads, which compresses better
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        Some(TableInfo {
+            key: signature_key(&format!("{}{}", white, black)),
+            key2: signature_key(&format!("{}{}", black, white)),
+            piece_count: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count: if white_leads { [white_pawns, black_pawns] } else { [black_pawns, white_pawns] }
+        })
+    }
+}
+
+/// The contents of a file, with one `PairsData` per side and file.
+struct TableData {
+    bytes: Vec<u8>,
+    sides: usize,
+    items: Vec<PairsData>,
+    /// Start of the DTZ value maps
+    map: usize
+}
+
+impl TableData {
+    fn get(&self, info: &TableInfo, stm: usize, file: usize) -> &PairsData {
+        &self.items[(stm % self.sides) * 4 + if info.has_pawns { file } else { 0 }]
+    }
+
+    fn load(path: &PathBuf, info: &TableInfo, kind: TableKind) -> Option<TableData> {
+        let bytes = fs::read(path).ok()?;
+        let magic = if kind == TableKind::Wdl { WDL_MAGIC } else { DTZ_MAGIC };
+        if bytes.get(0..4)? != magic {
+            println!("info string Corrupted tablebase file {}", path.display());
+            return None;
+        }
+        let table = Self::parse(bytes, info, kind);
+        if table.is_none() {
+            println!("info string Corrupted tablebase file {}", path.display());
+        }
+        table
+    }
+
+    fn parse(bytes: Vec<u8>, info: &TableInfo, kind: TableKind) -> Option<TableData> {
+        let e = encoding();
+        let mut data = 4;
+        let header = *bytes.get(data)?;
+        if (header & 2 != 0) != info.has_pawns || (header & 1 != 0) != (info.key != info.key2) {
+            return None;
+        }
+        data += 1;
+
+        let sides = if kind == TableKind::Wdl && info.key != info.key2 { 2 } else { 1 };
+        let files = if info.has_pawns { 4 } else { 1 };
+        let pp = info.has_pawns && info.pawn_count[1] > 0;
+        let mut items = vec![PairsData::default(); sides * 4];
+
+        for f in 0..files {
+            let order_byte = *bytes.get(data)?;
+            let pawn_order = if pp { *bytes.get(data + 1)? } else { 0xFF };
+            let order = [
+                [(order_byte & 0xF) as usize, (pawn_order & 0xF) as usize],
+                [(order_byte >> 4) as usize, (pawn_order >> 4) as usize]
+            ];
+            data += 1 + pp as usize;
+            for k in 0..info.piece_count {
+                let byte = *bytes.get(data)?;
+                for (i, d) in items.iter_mut().skip(f).step_by(4).enumerate() {
+                    d.pieces[k] = if i == 1 { byte >> 4 } else { byte & 0xF };
+                }
+                data += 1;
+            }
+            for (i, d) in items.iter_mut().skip(f).step_by(4).enumerate() {
+                set_groups(info, d, order[i], f, e);
+            }
+        }
+        data += data & 1;
+
+        for f in 0..files {
+            for i in 0..sides {
+                data = items[i * 4 + f].set_sizes(&bytes, data)?;
+            }
+        }
+
+        let map = data;
+        if kind == TableKind::Dtz {
+            for d in items.iter_mut().take(files) {
+                if d.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                if d.flags & FLAG_WIDE != 0 {
+                    data += data & 1;
+                    for i in 0..4 {
+                        d.map_idx[i] = (data - map) / 2 + 1;
+                        data += 2 * read_u16(&bytes, data)? as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        d.map_idx[i] = data - map + 1;
+                        data += *bytes.get(data)? as usize + 1;
+                    }
+                }
+            }
+            data += data & 1;
+        }
+
+        for f in 0..files {
+            for i in 0..sides {
+                let d = &mut items[i * 4 + f];
+                d.sparse_index = data;
+                data += d.sparse_index_size * 6;
+            }
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                let d = &mut items[i * 4 + f];
+                d.block_length = data;
+                data += d.block_length_size * 2;
+            }
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                let d = &mut items[i * 4 + f];
+                data = (data + 0x3F) & !0x3F;
+                d.data = data;
+                data += d.num_blocks * d.block_size;
+            }
+        }
+        if data > bytes.len() {
+            return None;
+        }
+        Some(TableData { bytes, sides, items, map })
+    }
+
+    /// Turns a stored DTZ value into plies.
+    fn map_dtz(&self, info: &TableInfo, file: usize, mut value: i32, wdl: i32) -> i32 {
+        const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+        let d = self.get(info, 0, file);
+        if d.flags & FLAG_MAPPED != 0 {
+            let idx = d.map_idx[WDL_MAP[(wdl + 2) as usize]] + value as usize;
+            value = if d.flags & FLAG_WIDE != 0 {
+                read_u16(&self.bytes, self.map + 2 * idx).unwrap_or(0) as i32
+            } else {
+                *self.bytes.get(self.map + idx).unwrap_or(&0) as i32
+            };
+        }
+        // Distances are stored in moves unless the table says plies
+        if (wdl == WDL_WIN && d.flags & FLAG_WIN_PLIES == 0)
+            || (wdl == WDL_LOSS && d.flags & FLAG_LOSS_PLIES == 0)
+            || wdl == WDL_CURSED_WIN || wdl == WDL_BLESSED_LOSS {
+            value *= 2;
+        }
+        value + 1
+    }
+}
+
+/// Splits the pieces into groups encoded together and computes the factor of
+/// each group in the index. Pieces of the same type and color form a group,
+/// except the leading group which holds the pawns of the leading color, or
+/// without pawns either three unique pieces or the two kings.
+fn set_groups(info: &TableInfo, d: &mut PairsData, order: [usize; 2], file: usize, e: &Encoding) {
+    let mut n = 0;
+    let mut first_len: i32 = if info.has_pawns { 0 } else if info.has_unique_pieces { 3 } else { 2 };
+    d.group_len[n] = 1;
+    for i in 1..info.piece_count {
+        first_len -= 1;
+        if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+            d.group_len[n] += 1;
+        } else {
+            n += 1;
+            d.group_len[n] = 1;
+        }
+    }
+    n += 1;
+    d.group_len[n] = 0;
+
+    // The groups are not necessarily encoded in sequence, `order` tells where
+    // the leading group and the other side's pawns go
+    let pp = info.has_pawns && info.pawn_count[1] > 0;
+    let mut next = if pp { 2 } else { 1 };
+    let mut free_squares = 64 - d.group_len[0] - if pp { d.group_len[1] } else { 0 };
+    let mut idx: u64 = 1;
+    let mut k = 0;
+    while next < n || k == order[0] || k == order[1] {
+        if k == order[0] {
+            d.group_idx[0] = idx;
+            idx *= if info.has_pawns {
+                e.lead_pawns_size[d.group_len[0]][file]
+            } else if info.has_unique_pieces {
+                31332
+            } else {
+                462
+            };
+        } else if k == order[1] {
+            d.group_idx[1] = idx;
+            idx *= e.binomial[d.group_len[1]][48 - d.group_len[0]];
+        } else {
+            d.group_idx[next] = idx;
+            idx *= e.binomial[d.group_len[next]][free_squares];
+            free_squares -= d.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    d.group_idx[n] = idx;
+}
+
+struct Table {
+    info: TableInfo,
+    wdl_path: PathBuf,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Option<TableData>>,
+    dtz: OnceLock<Option<TableData>>
+}
+
+impl Table {
+    fn data(&self, kind: TableKind) -> Option<&TableData> {
+        match kind {
+            TableKind::Wdl => self.wdl.get_or_init(|| TableData::load(&self.wdl_path, &self.info, kind)).as_ref(),
+            TableKind::Dtz => self.dtz.get_or_init(|| {
+                self.dtz_path.as_ref().and_then(|path| TableData::load(path, &self.info, kind))
+            }).as_ref()
+        }
+    }
+}
+
+fn tb_piece(pos: &Position, sq: usize) -> Option<u8> {
+    let piece = pos.get_peice(Square::from_index(sq))?;
+    Some(piece.p_type as u8 + 1 + if piece.is_white { 0 } else { 8 })
+}
+
+/// Whether either side could still castle, which tablebases do not cover.
+fn can_castle(pos: &Position) -> bool {
+    [(true, 0), (false, 7)].into_iter().any(|(is_white, rank)| {
+        let state = pos.castle_state(is_white);
+        let ours = pos.by_color(is_white);
+        let king_home = (0..8).any(|file| pos.kings.intersect(ours).get(Square::new(rank, file)));
+        let rook_home = |file| pos.rooks.intersect(ours).get(Square::new(rank, file));
+        king_home && ((state.can_short_castle && rook_home(state.short_rook_file))
+            || (state.can_long_castle && rook_home(state.long_rook_file)))
+    })
+}
+
+fn is_zeroing(pos: &Position, mv: Move) -> bool {
+    mv.is_capture() || pos.get_piece_type(mv.src()) == Some(PieceType::Pawn)
+}
+
+/// DTZ of the move before a capture or pawn move, given the WDL after it.
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        WDL_WIN => 1,
+        WDL_CURSED_WIN => 101,
+        WDL_BLESSED_LOSS => -101,
+        WDL_LOSS => -1,
+        _ => 0
+    }
+}
+
+fn legal_moves(pos: &mut Position) -> Option<MoveList> {
+    let mut moves = MoveList::new();
+    generate_legal_moves(&mut moves, pos).ok()?;
+    Some(moves)
+}
+
+pub struct Tablebases {
+    tables: Vec<Table>,
+    keys: HashMap<u64, usize>,
+    max_pieces: usize
+}
+
+impl Tablebases {
+    /// Finds every table in `paths`, a list of directories separated like `PATH`.
+    pub fn new(paths: &str) -> Tablebases {
+        let mut tablebases = Tablebases { tables: Vec::new(), keys: HashMap::new(), max_pieces: 0 };
+        let dirs: Vec<PathBuf> = env::split_paths(paths).filter(|p| p.is_dir()).collect();
+        for dir in &dirs {
+            let Ok(entries) = fs::read_dir(dir) else { continue };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_none_or(|ext| ext != "rtbw") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+                let Some(info) = TableInfo::from_name(name) else { continue };
+                if info.piece_count > MAX_PIECES || tablebases.keys.contains_key(&info.key) {
+                    continue;
+                }
+                let dtz_path = dirs.iter()
+                    .map(|d| d.join(format!("{}.rtbz", name)))
+                    .find(|p| p.is_file());
+                tablebases.max_pieces = tablebases.max_pieces.max(info.piece_count);
+                tablebases.keys.insert(info.key, tablebases.tables.len());
+                tablebases.keys.insert(info.key2, tablebases.tables.len());
+                tablebases.tables.push(Table {
+                    info,
+                    wdl_path: path.clone(),
+                    dtz_path,
+                    wdl: OnceLock::new(),
+                    dtz: OnceLock::new()
+                });
+            }
+        }
+        tablebases
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Largest number of pieces, kings included, of any table found.
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Whether `pos` has few enough pieces and no castling rights.
+    pub fn can_probe(&self, pos: &Position) -> bool {
+        pos.all_pieces().count() as usize <= self.max_pieces && !can_castle(pos)
+    }
+
+    /// Reads the value stored for `pos`, a WDL value or a DTZ in plies.
+    fn probe_table(&self, pos: &Position, kind: TableKind, wdl: i32, state: &mut ProbeState) -> Option<i32> {
+        if pos.all_pieces().count() == 2 {
+            return Some(WDL_DRAW);
+        }
+        let key = material_key(pos);
+        let table = &self.tables[*self.keys.get(&key)?];
+        let data = table.data(kind)?;
+        let info = &table.info;
+        let e = encoding();
+
+        let mut squares = [0usize; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+
+        // Tables are stored with the stronger side (the first in the name) as
+        // white and, when both sides are equal, only with white to move.
+        // Otherwise flip the colors and the board.
+        let symmetric_black_to_move = info.key == info.key2 && !pos.white_to_play;
+        let flip = symmetric_black_to_move || key != info.key;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip ^ !pos.white_to_play) as usize;
+
+        // With pawns there is one table per file of the leading pawn
+        let mut lead_pawns = 0;
+        let mut tb_file = 0;
+        if info.has_pawns {
+            let pawn = data.get(info, 0, 0).pieces[0] ^ flip_color;
+            for sq in 0..64 {
+                if tb_piece(pos, sq) == Some(pawn) {
+                    squares[size] = sq ^ flip_squares;
+                    size += 1;
+                }
+            }
+            lead_pawns = size;
+            let lead = (0..lead_pawns).rev().max_by_key(|&i| e.map_pawns[squares[i]]).unwrap_or(0);
+            squares.swap(0, lead);
+            tb_file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+
+        if kind == TableKind::Dtz {
+            let flags = data.get(info, stm, tb_file).flags;
+            if (flags & FLAG_STM) as usize != stm && (info.key != info.key2 || info.has_pawns) {
+                *state = ProbeState::ChangeStm;
+                return Some(0);
+            }
+        }
+
+        let lead_pawn = data.get(info, 0, 0).pieces[0] ^ flip_color;
+        for sq in 0..64 {
+            if let Some(piece) = tb_piece(pos, sq) && !(info.has_pawns && piece == lead_pawn) {
+                squares[size] = sq ^ flip_squares;
+                pieces[size] = piece ^ flip_color;
+                size += 1;
+            }
+        }
+
+        let d = data.get(info, stm, tb_file);
+
+        // Order the pieces the way the table lists them
+        for i in lead_pawns..size.saturating_sub(1) {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // Mirror so the leading piece is on files a-d
+        if squares[0] % 8 > 3 {
+            for sq in squares.iter_mut().take(size) {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx: u64;
+        if info.has_pawns {
+            idx = e.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|&sq| e.map_pawns[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += e.binomial[i][e.map_pawns[sq]];
+            }
+        } else {
+            // Mirror so the leading piece is on ranks 1-4, then below the a1-h8 diagonal
+            if squares[0] / 8 > 3 {
+                for sq in squares.iter_mut().take(size) {
+                    *sq ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                if off_a1h8(squares[i]) == 0 {
+                    continue;
+                }
+                if off_a1h8(squares[i]) > 0 {
+                    for sq in squares.iter_mut().take(size).skip(i) {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if info.has_unique_pieces {
+                let s = &squares;
+                let adjust1 = (s[1] > s[0]) as u64;
+                let adjust2 = (s[2] > s[0]) as u64 + (s[2] > s[1]) as u64;
+                let rank = |sq: usize| (sq / 8) as u64;
+                idx = if off_a1h8(s[0]) != 0 {
+                    (e.map_a1d1d4[s[0]] as u64 * 63 + (s[1] as u64 - adjust1)) * 62 + s[2] as u64 - adjust2
+                } else if off_a1h8(s[1]) != 0 {
+                    (6 * 63 + rank(s[0]) * 28 + e.map_b1h1h7[s[1]] as u64) * 62 + s[2] as u64 - adjust2
+                } else if off_a1h8(s[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + rank(s[0]) * 7 * 28 + (rank(s[1]) - adjust1) * 28
+                        + e.map_b1h1h7[s[2]] as u64
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(s[0]) * 7 * 6 + (rank(s[1]) - adjust1) * 6
+                        + (rank(s[2]) - adjust2)
+                };
+            } else {
+                idx = e.map_kk[e.map_a1d1d4[squares[0]]][squares[1]] as u64;
+            }
+        }
+
+        // The remaining groups, each as a combination of free squares
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = info.has_pawns && info.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort();
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&s| sq > s).count();
+                n += e.binomial[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = d.decompress(&data.bytes, idx).ok()?;
+        Some(match kind {
+            TableKind::Wdl => value - 2,
+            TableKind::Dtz => data.map_dtz(info, tb_file, value, wdl)
+        })
+    }
+
+    /// The WDL value of `pos`, also trying captures (and for DTZ pawn moves)
+    /// since the tables may store anything when one of those is best.
+    fn search(&self, pos: &mut Position, check_zeroing_moves: bool, state: &mut ProbeState) -> Option<i32> {
+        let moves = legal_moves(pos)?;
+        let mut best = WDL_LOSS;
+        let mut move_count = 0;
+        for &mv in &moves {
+            if !(mv.is_capture() || (check_zeroing_moves && is_zeroing(pos, mv))) {
+                continue;
+            }
+            move_count += 1;
+            pos.do_move(mv).ok()?;
+            let value = self.search(pos, false, state).map(|v| -v);
+            pos.undo_move().ok()?;
+            let value = value?;
+            if value > best {
+                best = value;
+                if value >= WDL_WIN {
+                    *state = ProbeState::ZeroingBestMove;
+                    return Some(value);
+                }
+            }
+        }
+
+        // With every move searched there is no need to probe, which also
+        // covers en passant positions that the tables do not store
+        let no_more_moves = move_count > 0 && move_count == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            self.probe_table(pos, TableKind::Wdl, WDL_DRAW, state)?
+        };
+
+        if best >= value {
+            *state = if best > WDL_DRAW || no_more_moves { ProbeState::ZeroingBestMove } else { ProbeState::Ok };
+            return Some(best);
+        }
+        *state = ProbeState::Ok;
+        Some(value)
+    }
+
+    /// Win/draw/loss for the side to move, from `WDL_LOSS` to `WDL_WIN`.
+    pub fn probe_wdl(&self, pos: &mut Position) -> Option<i32> {
+        let mut state = ProbeState::Ok;
+        self.search(pos, false, &mut state)
+    }
+
+    /// Distance to the next capture or pawn move in plies, positive when the
+    /// side to move wins and beyond 100 when the fifty-move rule saves the loser.
+    /// Can be one ply too long, except for positions right on the fifty-move edge.
+    pub fn probe_dtz(&self, pos: &mut Position) -> Option<i32> {
+        let mut state = ProbeState::Ok;
+        let wdl = self.search(pos, true, &mut state)?;
+        if wdl == WDL_DRAW {
+            return Some(0);
+        }
+        if state == ProbeState::ZeroingBestMove {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        let dtz = self.probe_table(pos, TableKind::Dtz, wdl, &mut state)?;
+        if state != ProbeState::ChangeStm {
+            let cursed = wdl == WDL_CURSED_WIN || wdl == WDL_BLESSED_LOSS;
+            return Some((dtz + if cursed { 100 } else { 0 }) * wdl.signum());
+        }
+
+        // The table only has the other side to move, look one ply ahead
+        let mut min_dtz = i32::MAX;
+        for mv in legal_moves(pos)? {
+            let zeroing = is_zeroing(pos, mv);
+            pos.do_move(mv).ok()?;
+            // For zeroing moves the WDL after the move gives the DTZ before it
+            let dtz = if zeroing {
+                self.probe_wdl(pos).map(|wdl| -dtz_before_zeroing(wdl))
+            } else {
+                self.probe_dtz(pos).map(|dtz| -dtz)
+            };
+            if dtz == Some(1) && pos.is_check().ok()? && legal_moves(pos)?.is_empty() {
+                min_dtz = 1;
+            }
+            pos.undo_move().ok()?;
+            let mut dtz = dtz?;
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+    }
+
+    /// Keeps the legal root `moves` that preserve the best tablebase result,
+    /// preferring quicker progress when the fifty-move rule is close. Returns
+    /// `None` if any move could not be probed.
+    pub fn filter_root_moves(&self, pos: &mut Position, moves: &[Move], use_rule50: bool) -> Option<Vec<Move>> {
+        let rule50 = pos.halfmove_clock as i32;
+        let mut ranked = Vec::new();
+        for &mv in moves {
+            pos.do_move(mv).ok()?;
+            let mut dtz = if pos.halfmove_clock == 0 {
+                self.probe_wdl(pos).map(|wdl| dtz_before_zeroing(-wdl))
+            } else {
+                // One ply further from the next zeroing move than after it
+                self.probe_dtz(pos).map(|dtz| -dtz - dtz.signum())
+            };
+            if dtz == Some(2) && pos.is_check().ok()? && legal_moves(pos)?.is_empty() {
+                dtz = Some(1);
+            }
+            pos.undo_move().ok()?;
+            let dtz = dtz?;
+
+            // Certain wins rank equally, as do losses unless a fifty-move draw is in sight
+            let rank = if dtz > 0 {
+                if dtz + rule50 <= 99 { 1000 } else { 1000 - (dtz + rule50) }
+            } else if dtz < 0 {
+                if -dtz * 2 + rule50 < 100 { -1000 } else { -1000 + (-dtz + rule50) }
+            } else {
+                0
+            };
+            // Without the fifty-move rule cursed wins are wins and blessed losses losses
+            let rank = if use_rule50 { rank } else { 1000 * rank.signum() };
+            ranked.push((mv, rank));
+        }
+        let best = ranked.iter().map(|&(_, rank)| rank).max()?;
+        Some(ranked.into_iter().filter(|&(_, rank)| rank == best).map(|(mv, _)| mv).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_tables() {
+        let e = encoding();
+        assert_eq!(e.binomial[2][5], 10);
+        assert_eq!(e.binomial[3][48], 17296);
+        // a2 is the first leading pawn square, its mirror h2 the next
+        assert_eq!((e.map_pawns[8], e.map_pawns[15]), (47, 46));
+        assert_eq!(e.lead_pawns_size[1], [6; 4]);
+
+        // Every legal king pair gets its own code from 0 to 461, 0 being
+        // indistinguishable from the squares left out
+        let mut codes: Vec<usize> = e.map_kk.iter().flatten().copied().filter(|&code| code != 0).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes, (1..462).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn group_sizes() {
+        let e = encoding();
+        // Three unique pieces share the leading group
+        let info = TableInfo::from_name("KRvK").unwrap();
+        let mut d = PairsData { pieces: [6, 4, 14, 0, 0, 0, 0], ..PairsData::default() };
+        set_groups(&info, &mut d, [0, 0xF], 0, e);
+        assert_eq!(&d.group_len[..2], &[3, 0]);
+        assert_eq!(d.group_idx[1], 31332);
+
+        // Two identical pieces after the kings form a group of their own
+        let info = TableInfo::from_name("KNNvK").unwrap();
+        let mut d = PairsData { pieces: [6, 14, 2, 2, 0, 0, 0], ..PairsData::default() };
+        set_groups(&info, &mut d, [0, 0xF], 0, e);
+        assert_eq!(&d.group_len[..3], &[2, 2, 0]);
+        assert_eq!(d.group_idx[1], 462);
+        assert_eq!(d.group_idx[2], 462 * e.binomial[2][62]);
+    }
+
+    #[test]
+    fn map_dtz_values() {
+        let info = TableInfo::from_name("KRvK").unwrap();
+        let mut data = TableData { bytes: vec![0, 3, 9, 4, 5], sides: 1, items: vec![PairsData::default(); 4], map: 0 };
+
+        // Stored in moves unless the table says plies
+        assert_eq!(data.map_dtz(&info, 0, 5, WDL_WIN), 11);
+        assert_eq!(data.map_dtz(&info, 0, 5, WDL_LOSS), 11);
+        data.items[0].flags = FLAG_WIN_PLIES;
+        assert_eq!(data.map_dtz(&info, 0, 5, WDL_WIN), 6);
+        assert_eq!(data.map_dtz(&info, 0, 5, WDL_LOSS), 11);
+        assert_eq!(data.map_dtz(&info, 0, 5, WDL_CURSED_WIN), 11);
+
+        // Mapped values are looked up per result, one byte each
+        data.items[0].flags = FLAG_MAPPED | FLAG_WIN_PLIES;
+        data.items[0].map_idx = [1, 3, 0, 0];
+        assert_eq!(data.map_dtz(&info, 0, 1, WDL_WIN), 10);
+        assert_eq!(data.map_dtz(&info, 0, 1, WDL_LOSS), 11);
+    }
+
+    #[test]
+    fn decompress_out_of_range() {
+        // The sparse index entry points before the first block
+        let d = PairsData { span: 16, num_blocks: 1, block_length: 6, ..PairsData::default() };
+        assert!(d.decompress(&[0; 8], 0).is_err());
+
+        // Or past the last one
+        let d = PairsData { span: 16, num_blocks: 1, block_length: 6, ..PairsData::default() };
+        let mut bytes = [0u8; 8];
+        bytes[4] = 40;
+        assert!(d.decompress(&bytes, 15).is_err());
+    }
+
+    #[test]
+    fn symlen_out_of_range() {
+        let pair = |left: usize, right: usize| [left as u8, (left >> 8 | (right & 0xF) << 4) as u8, (right >> 4) as u8];
+        let mut visited = [false; 2];
+
+        // Symbol 0 is a leaf and symbol 1 pairs it with itself
+        let mut d = PairsData { symlen: vec![0; 2], ..PairsData::default() };
+        assert_eq!(d.set_symlen(&[pair(7, 0xFFF), pair(0, 0)].concat(), 1, &mut visited), Some(1));
+
+        // A pair naming a symbol the table does not have
+        let mut d = PairsData { symlen: vec![0; 2], ..PairsData::default() };
+        visited = [false; 2];
+        assert_eq!(d.set_symlen(&[pair(7, 0xFFF), pair(0, 2)].concat(), 1, &mut visited), None);
+    }
+}
@@ -0,0 +1,211 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::endgame;
+use crate::error::Error;
+use crate::eval::{phase, trace, Score, MAX_PHASE};
+use crate::params::{EvalParams, PARAM_COUNT};
+use crate::position::Position;
+
+/// A training position reduced to the parameters its evaluation depends on.
+/// The evaluation is linear in the parameters, so the white-side score is
+/// `sum(coef * (mg * phase + eg * (1 - phase)))` over `coefficients`.
+struct Entry {
+    coefficients: Vec<(usize, f32)>,
+    phase: f32,
+    result: f32
+}
+
+impl Entry {
+    fn evaluate(&self, params: &EvalParams) -> f32 {
+        self.coefficients.iter().fold(0f32, |acc, &(i, coef)| {
+            acc + coef * (params[i].mg * self.phase + params[i].eg * (1f32 - self.phase))
+        })
+    }
+}
+
+struct TuneOptions {
+    dataset: String,
+    output: String,
+    initial: Option<String>,
+    iterations: u32,
+    learning_rate: f32
+}
+
+fn parse_options(args: &[String]) -> Result<TuneOptions, Error> {
+    let mut options = TuneOptions {
+        dataset: String::new(),
+        output: "params.txt".to_string(),
+        initial: None,
+        iterations: 1000,
+        learning_rate: 0.01
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or(Error::Parse(format!("Missing value for {}", arg)));
+        match arg.as_str() {
+            "--output" => options.output = value()?,
+            "--params" => options.initial = Some(value()?),
+            "--iterations" => options.iterations = value()?.parse().map_err(|_| Error::Parse(String::from("Invalid iteration count")))?,
+            "--rate" => options.learning_rate = value()?.parse().map_err(|_| Error::Parse(String::from("Invalid learning rate")))?,
+            _ if options.dataset.is_empty() => options.dataset = arg.clone(),
+            _ => return Err(Error::Parse(format!("Unexpected argument '{}'", arg)))
+        }
+    }
+    if options.dataset.is_empty() {
+        return Err(Error::Parse(String::from("Usage: tune <dataset> [--output path] [--params path] [--iterations n] [--rate r]")));
+    }
+    Ok(options)
+}
+
+/// Reads a game result written as a PGN result (`1-0`, `0-1`, `1/2-1/2`),
+/// possibly quoted or behind an EPD opcode such as `c9 "1-0";`, or as a
+/// score of 0, 0.5 or 1, possibly in brackets.
+fn parse_result(text: &str) -> Option<f32> {
+    let text = text.trim().trim_end_matches(';');
+    let text = text.strip_prefix("c9").unwrap_or(text).trim().trim_matches('"');
+    let text = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')).unwrap_or(text).trim();
+    let result = match text {
+        "1-0" => 1.0,
+        "0-1" => 0.0,
+        "1/2-1/2" => 0.5,
+        _ => text.parse().ok()?
+    };
+    [0.0, 0.5, 1.0].contains(&result).then_some(result)
+}
+
+/// Splits a dataset line into its position and game result. Lines are either
+/// `datagen` text records (`<fen> | <score> | <result>`) or a FEN, with or
+/// without its move counters, followed by the result.
+fn parse_line(line: &str) -> Result<(Position, f32), Error> {
+    let (fen, result) = match line.split_once('|') {
+        Some((fen, rest)) => (fen.to_string(), rest.rsplit('|').next().unwrap_or(rest).to_string()),
+        None => {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 5 {
+                return Err(Error::Parse(format!("Expected a FEN and a result in '{}'", line)));
+            }
+            // The halfmove and fullmove counters are optional
+            let counters = fields[4..fields.len() - 1].iter().take(2).take_while(|f| f.parse::<u32>().is_ok()).count();
+            let fen_end = 4 + counters;
+            (fields[..fen_end].join(" "), fields[fen_end..].join(" "))
+        }
+    };
+    let pos = Position::from_fen(&fen)?;
+    let result = parse_result(&result).ok_or(Error::Parse(format!("Invalid game result '{}'", result.trim())))?;
+    Ok((pos, result))
+}
+
+fn load_dataset(path: &str, params: &EvalParams) -> Result<Vec<Entry>, Error> {
+    let file = File::open(path).map_err(|e| Error::Io(format!("Cannot open {}: {}", path, e)))?;
+    let mut entries = Vec::new();
+    for (line_number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (pos, result) = parse_line(&line).map_err(|e| Error::Parse(format!("{}:{}: {}", path, line_number + 1, e)))?;
+        // Specialized endgame knowledge replaces or scales the parameters,
+        // so these positions say nothing about them
+        if pos.kings.count() != 2 || endgame::probe(&pos).is_some() {
+            continue;
+        }
+
+        let trace = trace(&pos, params);
+        entries.push(Entry {
+            coefficients: trace.coefficients.iter()
+                .enumerate()
+                .filter(|&(_, &c)| c != 0)
+                .map(|(i, &c)| (i, c as f32))
+                .collect(),
+            phase: phase(&pos) as f32 / MAX_PHASE as f32,
+            result
+        });
+    }
+    Ok(entries)
+}
+
+fn sigmoid(k: f32, score: f32) -> f32 {
+    1f32 / (1f32 + (-k * score).exp())
+}
+
+fn mean_error(entries: &[Entry], params: &EvalParams, k: f32) -> f32 {
+    let total = entries.iter().fold(0f64, |acc, e| {
+        let diff = e.result - sigmoid(k, e.evaluate(params));
+        acc + (diff * diff) as f64
+    });
+    (total / entries.len() as f64) as f32
+}
+
+/// Finds the sigmoid scaling that best maps the current evaluation to results.
+fn fit_scaling(entries: &[Entry], params: &EvalParams) -> f32 {
+    let (mut low, mut high) = (0.01f32, 10f32);
+    for _ in 0..60 {
+        let a = low + (high - low) / 3f32;
+        let b = high - (high - low) / 3f32;
+        if mean_error(entries, params, a) < mean_error(entries, params, b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    (low + high) / 2f32
+}
+
+fn gradient(entries: &[Entry], params: &EvalParams, k: f32) -> Vec<Score> {
+    let mut grad = vec![Score::default(); PARAM_COUNT];
+    for e in entries {
+        let sig = sigmoid(k, e.evaluate(params));
+        let d = (sig - e.result) * sig * (1f32 - sig);
+        for &(i, coef) in &e.coefficients {
+            grad[i].mg += d * coef * e.phase;
+            grad[i].eg += d * coef * (1f32 - e.phase);
+        }
+    }
+    let scale = 2f32 * k / entries.len() as f32;
+    grad.iter().map(|g| Score::new(g.mg * scale, g.eg * scale)).collect()
+}
+
+/// Tunes every evaluation parameter against a labeled dataset with Adam,
+/// writing the result to a parameter file the engine can load.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let options = parse_options(args)?;
+    let mut params = match &options.initial {
+        Some(path) => EvalParams::load(path)?,
+        None => EvalParams::default()
+    };
+
+    let entries = load_dataset(&options.dataset, &params)?;
+    if entries.is_empty() {
+        return Err(Error::Parse(format!("No positions in {}", options.dataset)));
+    }
+    println!("Loaded {} positions", entries.len());
+
+    let k = fit_scaling(&entries, &params);
+    println!("Scaling constant K = {:.4}, error {:.6}", k, mean_error(&entries, &params, k));
+
+    const BETA1: f32 = 0.9;
+    const BETA2: f32 = 0.999;
+    let mut m = vec![Score::default(); PARAM_COUNT];
+    let mut v = vec![Score::default(); PARAM_COUNT];
+    for iteration in 1..=options.iterations {
+        let grad = gradient(&entries, &params, k);
+        let correction1 = 1f32 - BETA1.powi(iteration as i32);
+        let correction2 = 1f32 - BETA2.powi(iteration as i32);
+        for i in 0..PARAM_COUNT {
+            m[i].mg = BETA1 * m[i].mg + (1f32 - BETA1) * grad[i].mg;
+            m[i].eg = BETA1 * m[i].eg + (1f32 - BETA1) * grad[i].eg;
+            v[i].mg = BETA2 * v[i].mg + (1f32 - BETA2) * grad[i].mg * grad[i].mg;
+            v[i].eg = BETA2 * v[i].eg + (1f32 - BETA2) * grad[i].eg * grad[i].eg;
+            params[i].mg -= options.learning_rate * (m[i].mg / correction1) / ((v[i].mg / correction2).sqrt() + 1e-8);
+            params[i].eg -= options.learning_rate * (m[i].eg / correction1) / ((v[i].eg / correction2).sqrt() + 1e-8);
+        }
+
+        if iteration % 50 == 0 || iteration == options.iterations {
+            println!("Iteration {} error {:.6}", iteration, mean_error(&entries, &params, k));
+            params.save(&options.output)?;
+        }
+    }
+    println!("Wrote {}", options.output);
+    Ok(())
+}
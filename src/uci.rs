@@ -0,0 +1,261 @@
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::bench;
+use crate::book::Book;
+use crate::error::Error;
+use crate::eval::trace;
+use crate::mate::{mate_thread, MateSolver};
+use crate::moves::Move;
+use crate::params::EvalParams;
+use crate::position::Position;
+use crate::search::{iterative_deepening, skill_level_from_elo, time_budget, SearchContext, SearchThread, MAX_ELO,
+    MAX_SKILL_LEVEL, MIN_ELO};
+use crate::syzygy::Tablebases;
+
+/// Parses `setoption name <name> value <value>` into the option name and value.
+fn parse_setoption(parts: &[&str]) -> Option<(String, String)> {
+    let name_index = parts.iter().position(|&p| p == "name")?;
+    let value_index = parts.iter().position(|&p| p == "value").unwrap_or(parts.len());
+    let name = parts.get(name_index + 1..value_index)?.join(" ");
+    let value = parts.get(value_index + 1..).map(|v| v.join(" ")).unwrap_or_default();
+    Some((name, value))
+}
+
+/// Parses `position [startpos | fen <fen>] [moves <move>...]`. With `chess960`
+/// castling moves are written as the king taking its own rook.
+fn parse_position(parts: &[&str], chess960: bool) -> Result<Position, Error> {
+    let moves_index = parts.iter().position(|&p| p == "moves").unwrap_or(parts.len());
+    let mut pos = match parts.get(1) {
+        Some(&"fen") => Position::from_fen(&parts[2..moves_index].join(" "))?,
+        _ => Position::start()
+    };
+    pos.chess960 |= chess960;
+    for move_part in parts.iter().skip(moves_index + 1) {
+        pos.do_move(pos.parse_legal_move(move_part)?)?;
+    }
+    Ok(pos)
+}
+
+/// Parameters of `go` that end a `searchmoves` list.
+const GO_KEYWORDS: [&str; 11] = ["searchmoves", "ponder", "wtime", "btime", "winc", "binc", "movestogo",
+    "depth", "nodes", "mate", "movetime"];
+
+/// Parses the moves after `go searchmoves`, reporting and leaving out the
+/// ones that cannot be played. Without any legal one every move is searched.
+fn parse_search_moves(parts: &[&str], pos: &Position) -> Vec<Move> {
+    let Some(index) = parts.iter().position(|&p| p == "searchmoves") else {
+        return Vec::new();
+    };
+    let mut moves = Vec::new();
+    for part in parts[index + 1..].iter().take_while(|p| !GO_KEYWORDS.contains(p) && **p != "infinite") {
+        match pos.parse_legal_move(part) {
+            Ok(mv) => moves.push(mv),
+            Err(e) => println!("info string Ignoring searchmoves {}: {}", part, e)
+        }
+    }
+    if moves.is_empty() {
+        println!("info string No legal searchmoves, searching every move");
+    }
+    moves
+}
+
+/// Picks the search time from `go movetime <ms>` or the clock in
+/// `go wtime <ms> btime <ms> [winc <ms>] [binc <ms>]`.
+fn parse_go_time(parts: &[&str], white_to_play: bool) -> Duration {
+    let value = |name: &str| -> Option<u64> {
+        let index = parts.iter().position(|&p| p == name)?;
+        parts.get(index + 1)?.parse().ok()
+    };
+    if let Some(movetime) = value("movetime") {
+        return Duration::from_millis(movetime);
+    }
+    let (time, increment) = if white_to_play { ("wtime", "winc") } else { ("btime", "binc") };
+    match value(time) {
+        Some(remaining) => time_budget(Duration::from_millis(remaining), Duration::from_millis(value(increment).unwrap_or(0))),
+        None => Duration::from_secs(6)
+    }
+}
+
+/// Locks the search context. A search that panicked is reported when its
+/// thread is stopped, and leaves the context usable.
+fn lock(ctx: &Mutex<SearchContext>) -> MutexGuard<'_, SearchContext> {
+    ctx.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Talks UCI on standard input and output until `quit` or the end of input.
+pub fn run() -> Result<(), Error> {
+    let input = io::stdin();
+
+    let mut line = String::new();
+
+    let mut pos = Position::start();
+
+    let mut search_ctx = SearchContext::new();
+    search_ctx.report_currmove = true;
+    let ctx = Arc::new(Mutex::new(search_ctx));
+
+    let mut book: Option<Book> = None;
+    let mut own_book = false;
+    let mut book_depth = 20;
+    let mut rng = rand::rng();
+
+    // UCI_Elo takes over from Skill Level while UCI_LimitStrength is on
+    let mut skill_level = MAX_SKILL_LEVEL;
+    let mut limit_strength = false;
+    let mut uci_elo = MAX_ELO;
+    let mut mate_solver = MateSolver::AlphaBeta;
+    let mut chess960 = false;
+
+    // The search runs on its own thread so `stop` and `isready` are answered meanwhile
+    let mut search: Option<SearchThread> = None;
+
+    loop {
+        line.clear();
+        // The GUI closing our input is as good as `quit`
+        if input.read_line(&mut line)? == 0 {
+            if let Some(search) = search.take() {
+                search.stop();
+            }
+            return Ok(());
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let Some(&command) = parts.first() else { continue };
+
+        // Anything that changes the search context or starts over ends the running search
+        if matches!(command, "stop" | "quit" | "go" | "ucinewgame" | "setoption" | "eval" | "bench")
+            && let Some(search) = search.take() {
+            search.stop();
+        }
+
+        match command {
+            "uci" => {
+                println!("id name CrabGambit");
+                println!("id author Ben Hess");
+                println!("option name UCI_Chess960 type check default false");
+                println!("option name MultiPV type spin default 1 min 1 max 256");
+                println!("option name Skill Level type spin default {} min 0 max {}", MAX_SKILL_LEVEL, MAX_SKILL_LEVEL);
+                println!("option name UCI_LimitStrength type check default false");
+                println!("option name UCI_Elo type spin default {} min {} max {}", MAX_ELO, MIN_ELO, MAX_ELO);
+                println!("option name MateSolver type combo default AlphaBeta var AlphaBeta var ProofNumber");
+                println!("option name EvalFile type string default <empty>");
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name SyzygyProbeLimit type spin default 7 min 0 max 7");
+                println!("option name Syzygy50MoveRule type check default true");
+                println!("option name OwnBook type check default false");
+                println!("option name BookFile type string default <empty>");
+                println!("option name BookDepth type spin default 20 min 1 max 100");
+                println!("uciok");
+            },
+            "isready" => {
+                println!("readyok");
+            },
+            "bench" => {
+                let args: Vec<String> = parts[1..].iter().map(|p| p.to_string()).collect();
+                if let Err(e) = bench::run(&args) {
+                    println!("info string {}", e);
+                }
+            },
+            "quit" => {
+                return Ok(());
+            },
+            "stop" => {},
+            "ucinewgame" => {
+                pos = Position::start();
+                pos.chess960 = chess960;
+            },
+            "setoption" => {
+                let Some((name, value)) = parse_setoption(&parts) else { continue };
+                let mut ctx = lock(&ctx);
+                let is_empty = value.is_empty() || value == "<empty>";
+                match name.as_str() {
+                    "UCI_Chess960" => chess960 = value == "true",
+                    "MultiPV" => match value.parse() {
+                        Ok(multi_pv) if (1..=256).contains(&multi_pv) => ctx.multi_pv = multi_pv,
+                        _ => println!("info string Invalid MultiPV {}", value)
+                    },
+                    "Skill Level" => match value.parse() {
+                        Ok(level) if level <= MAX_SKILL_LEVEL => skill_level = level,
+                        _ => println!("info string Invalid skill level {}", value)
+                    },
+                    "UCI_LimitStrength" => limit_strength = value == "true",
+                    "UCI_Elo" => match value.parse() {
+                        Ok(elo) if (MIN_ELO..=MAX_ELO).contains(&elo) => uci_elo = elo,
+                        _ => println!("info string Invalid Elo {}", value)
+                    },
+                    "MateSolver" => match value.as_str() {
+                        "AlphaBeta" => mate_solver = MateSolver::AlphaBeta,
+                        "ProofNumber" => mate_solver = MateSolver::ProofNumber,
+                        _ => println!("info string Unknown mate solver {}", value)
+                    },
+                    "EvalFile" if is_empty => ctx.params = EvalParams::default(),
+                    "EvalFile" => match EvalParams::load(&value) {
+                        Ok(params) => ctx.params = params,
+                        Err(e) => println!("info string {}", e)
+                    },
+                    "SyzygyPath" if is_empty => ctx.tablebases = None,
+                    "SyzygyPath" => {
+                        let tablebases = Tablebases::new(&value);
+                        println!("info string Found {} tablebases with up to {} pieces", tablebases.len(), tablebases.max_pieces());
+                        ctx.tablebases = Some(Arc::new(tablebases));
+                    },
+                    "SyzygyProbeLimit" => match value.parse() {
+                        Ok(limit) => ctx.tb_probe_limit = limit,
+                        Err(_) => println!("info string Invalid probe limit {}", value)
+                    },
+                    "Syzygy50MoveRule" => ctx.tb_rule50 = value == "true",
+                    "OwnBook" => own_book = value == "true",
+                    "BookFile" if is_empty => book = None,
+                    "BookFile" => match Book::load(&value) {
+                        Ok(loaded) => {
+                            println!("info string Loaded book with {} entries", loaded.len());
+                            book = Some(loaded);
+                        },
+                        Err(e) => println!("info string {}", e)
+                    },
+                    "BookDepth" => match value.parse() {
+                        Ok(depth) => book_depth = depth,
+                        Err(_) => println!("info string Invalid book depth {}", value)
+                    },
+                    _ => {}
+                }
+                ctx.skill_level = if limit_strength { skill_level_from_elo(uci_elo) } else { skill_level };
+            },
+            "position" => {
+                // A bad command leaves the previous position in place
+                match parse_position(&parts, chess960) {
+                    Ok(new_pos) => pos = new_pos,
+                    Err(e) => println!("info string {}", e)
+                }
+            },
+            "eval" => {
+                println!("{}", pos);
+                println!();
+                println!("{}", trace(&pos, &lock(&ctx).params));
+            },
+            "go" => {
+                let search_moves = parse_search_moves(&parts, &pos);
+
+                // Book moves are only played for the first `book_depth` moves of the game
+                let book_move = book.as_ref()
+                    .filter(|_| own_book && pos.fullmove_number <= book_depth)
+                    .and_then(|book| book.probe(&pos, &mut rng))
+                    .filter(|mv| search_moves.is_empty() || search_moves.contains(mv));
+                lock(&ctx).search_moves = search_moves;
+                let max_time = parse_go_time(&parts, pos.white_to_play);
+
+                // `go mate <n>` runs the mate solver, falling back to the book or a normal search without a mate
+                let mate_moves = parts.iter().position(|&p| p == "mate").and_then(|i| parts.get(i + 1)?.parse().ok());
+                if let Some(max_moves) = mate_moves {
+                    search = Some(mate_thread(ctx.clone(), pos.snapshot(), max_moves, mate_solver, book_move, max_time));
+                } else if let Some(mv) = book_move {
+                    println!("bestmove {}", mv);
+                } else {
+                    search = Some(iterative_deepening(ctx.clone(), pos.snapshot(), max_time));
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use crab_gambit::bitboard::Bitboard;
+use crab_gambit::square::Square;
+
+/// `rank` and `file` once had their masks swapped, so each returned the
+/// other's squares.
+#[test]
+fn rank_and_file_masks() {
+    for i in 0..8 {
+        let rank: Vec<Square> = Bitboard::rank(i).into_iter().collect();
+        let file: Vec<Square> = Bitboard::file(i).into_iter().collect();
+        assert_eq!(rank, (0..8).map(|f| Square::new(i, f)).collect::<Vec<_>>());
+        assert_eq!(file, (0..8).map(|r| Square::new(r, i)).collect::<Vec<_>>());
+    }
+    assert!(Bitboard::rank(-1).is_empty() && Bitboard::rank(8).is_empty());
+    assert!(Bitboard::file(-1).is_empty() && Bitboard::file(8).is_empty());
+}
@@ -0,0 +1,41 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crab_gambit::moves::MoveList;
+use crab_gambit::{generate_legal_moves, Position};
+
+const FENS: [&str; 5] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9"
+];
+
+/// Plays random games and checks at every ply that each legal move, once
+/// undone, leaves the position exactly as it was.
+#[test]
+fn undo_restores_position() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for fen in FENS {
+        for _ in 0..8 {
+            let mut pos = Position::from_fen(fen).unwrap();
+            for _ in 0..60 {
+                let mut moves = MoveList::new();
+                generate_legal_moves(&mut moves, &mut pos).unwrap();
+                if moves.is_empty() {
+                    break;
+                }
+                for &mv in moves.iter() {
+                    let before = pos.clone();
+                    pos.do_move(mv).unwrap();
+                    let after = Position::from_fen(&pos.to_fen()).unwrap();
+                    assert_eq!(pos.polyglot_key(), after.polyglot_key(), "hash after {} in {}", mv, before.to_fen());
+                    pos.undo_move().unwrap();
+                    assert!(pos == before, "undoing {} in {} gave {}", mv, before.to_fen(), pos.to_fen());
+                }
+                pos.do_move(moves[rng.random_range(0..moves.len())]).unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use crab_gambit::moves::MoveList;
+use crab_gambit::{generate_legal_moves, Position};
+
+fn perft(pos: &mut Position, depth: u32) -> u64 {
+    let mut moves = MoveList::new();
+    generate_legal_moves(&mut moves, pos).unwrap();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    let mut nodes = 0;
+    for &mv in moves.iter() {
+        pos.do_move(mv).unwrap();
+        nodes += perft(pos, depth - 1);
+        pos.undo_move().unwrap();
+    }
+    nodes
+}
+
+fn assert_perft(fen: &str, counts: &[u64]) {
+    let mut pos = Position::from_fen(fen).unwrap();
+    for (depth, &expected) in counts.iter().enumerate() {
+        assert_eq!(perft(&mut pos, depth as u32 + 1), expected, "perft {} of {}", depth + 1, fen);
+    }
+}
+
+#[test]
+fn start_position() {
+    assert_perft("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &[20, 400, 8902, 197281]);
+}
+
+/// The bishop covers f1 and can take the h1 rook, so white may neither
+/// castle through check nor castle short once the rook is gone.
+#[test]
+fn castling_through_check() {
+    assert_perft("r3k2r/8/8/8/8/8/6b1/R3K2R w KQ - 0 1", &[24, 657, 15599]);
+}
+
+/// Castling through and out of check, castling without the rook and black
+/// castling on its own home rank.
+#[test]
+fn kiwipete() {
+    assert_perft("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2039, 97862]);
+}
+
+#[test]
+fn en_passant_pins() {
+    assert_perft("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43238]);
+}
+
+/// Promotions that capture, for both colors.
+#[test]
+fn position_4() {
+    assert_perft("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9467, 422333]);
+    assert_perft("r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1", &[6, 264, 9467]);
+}
+
+#[test]
+fn position_5() {
+    assert_perft("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1486, 62379]);
+}
+
+#[test]
+fn chess960() {
+    assert_perft("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", &[21, 528, 12189]);
+    assert_perft("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", &[21, 807, 18002]);
+}
+
+/// The halfmove clock resets on pawn moves and captures, and the fullmove
+/// number goes up after black plays.
+#[test]
+fn move_counters() {
+    let mut pos = Position::start();
+    for (mv, fen) in [
+        ("g1f3", "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1"),
+        ("b8c6", "r1bqkbnr/pppppppp/2n5/8/8/5N2/PPPPPPPP/RNBQKB1R w KQkq - 2 2"),
+        ("e2e4", "r1bqkbnr/pppppppp/2n5/8/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq e3 0 2"),
+        ("c6d4", "r1bqkbnr/pppppppp/8/8/3nP3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 1 3"),
+        ("f3d4", "r1bqkbnr/pppppppp/8/8/3NP3/8/PPPP1PPP/RNBQKB1R b KQkq - 0 3")
+    ] {
+        pos.do_move(pos.parse_legal_move(mv).unwrap()).unwrap();
+        assert_eq!(pos.to_fen(), fen);
+    }
+}
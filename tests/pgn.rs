@@ -0,0 +1,74 @@
+use crab_gambit::pgn::read_games;
+
+/// Move numbers glued to the moves, symbolic annotations and a variation
+/// inside a variation, starting from black's twelfth move.
+const INPUT: &str = r#"[Event "Test"]
+[Site "?"]
+[SetUp "1"]
+[FEN "r1bqkb1r/pppp1ppp/2n5/8/3nP3/8/PPP2PPP/RNBQKBNR b KQkq - 0 12"]
+[Result "*"]
+
+{Start} 12...Nf3+ $1 13.gxf3 {forced}
+(13.Ke2 Nfd4+ (13...Nxg1?! 14.Rxg1) 14.Ke1) 13...Qh4 14.Be2!? *
+"#;
+
+const EXPORT: &str = r#"[Event "Test"]
+[Site "?"]
+[SetUp "1"]
+[FEN "r1bqkb1r/pppp1ppp/2n5/8/3nP3/8/PPP2PPP/RNBQKBNR b KQkq - 0 12"]
+[Result "*"]
+
+{Start} 12... Nf3+ $1 13. gxf3 {forced} (13. Ke2 Nfd4+ (13... Nxg1+ $6 14. Rxg1)
+14. Ke1) 13... Qh4 14. Be2 $5 *
+"#;
+
+#[test]
+fn game_tree() {
+    let games = read_games(INPUT).unwrap();
+    assert_eq!(games.len(), 1);
+    let game = &games[0];
+    assert_eq!(game.tags.len(), 5);
+    assert_eq!(game.tag("SetUp"), Some("1"));
+    assert_eq!(game.result, "*");
+
+    let moves = &game.moves;
+    assert_eq!(moves.len(), 4);
+    assert_eq!(moves[0].mv.to_string(), "d4f3");
+    assert_eq!(moves[0].comment_before.as_deref(), Some("Start"));
+    assert_eq!(moves[0].nags, [1]);
+    assert_eq!(moves[1].comment.as_deref(), Some("forced"));
+    assert_eq!(moves[3].nags, [5]);
+
+    // The variation replaces 13.gxf3 and holds one of its own
+    let variation = &moves[1].variations[0];
+    assert_eq!(variation.len(), 3);
+    assert_eq!(variation[0].mv.to_string(), "e1e2");
+    assert_eq!(variation[1].variations[0][0].mv.to_string(), "f3g1");
+    assert_eq!(variation[1].variations[0][0].nags, [6]);
+}
+
+#[test]
+fn round_trip() {
+    let game = &read_games(INPUT).unwrap()[0];
+    assert_eq!(game.to_pgn().unwrap(), EXPORT);
+    // The export reads back to the same game
+    assert_eq!(read_games(EXPORT).unwrap()[0].to_pgn().unwrap(), EXPORT);
+}
+
+#[test]
+fn several_games() {
+    let text = format!("{}\n1. e4 e5 2. Nf3 1-0\n\n[Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4# 0-1\n", INPUT);
+    let games = read_games(&text).unwrap();
+    assert_eq!(games.len(), 3);
+    assert_eq!((games[1].moves.len(), games[1].result.as_str()), (3, "1-0"));
+    assert_eq!((games[2].moves.len(), games[2].result.as_str()), (4, "0-1"));
+    assert!(games[2].to_pgn().unwrap().ends_with("1. f3 e5 2. g4 Qh4# 0-1\n"));
+}
+
+#[test]
+fn errors() {
+    assert!(read_games("1. e4 e5 2. Ke3 *").is_err(), "illegal move");
+    assert!(read_games("1. e4 (1. d4 *").is_err(), "unterminated variation");
+    assert!(read_games("1. e4 e5) *").is_err(), "unmatched parenthesis");
+    assert!(read_games("1. e4 {never closed *").is_err(), "unterminated comment");
+}
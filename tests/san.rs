@@ -0,0 +1,79 @@
+use crab_gambit::{Error, Move, Position};
+
+fn uci_move(pos: &Position, uci: &str) -> Move {
+    pos.parse_legal_move(uci).unwrap()
+}
+
+/// Formats the move, then checks the text reads back as the same move.
+fn assert_san(fen: &str, uci: &str, san: &str) {
+    let pos = Position::from_fen(fen).unwrap();
+    let mv = uci_move(&pos, uci);
+    assert_eq!(pos.to_san(mv).unwrap(), san, "{} in {}", uci, fen);
+    assert!(pos.parse_san(san).unwrap() == mv, "{} in {}", san, fen);
+}
+
+#[test]
+fn disambiguation() {
+    // Both knights reach d2, the files tell them apart
+    assert_san("rnbqkbnr/ppp1pppp/8/3p4/3P4/5N2/PPP1PPPP/RNBQKB1R w KQkq - 0 3", "b1d2", "Nbd2");
+    assert_san("4k3/8/8/2N1N3/8/8/8/4K3 w - - 0 1", "c5d3", "Ncd3");
+    // Rooks on one file need the rank
+    assert_san("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3", "R1a3");
+    assert_san("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a5a3", "R5a3");
+    // One queen shares the file, another the rank, so only the square will do
+    assert_san("k6K/8/8/1Q3Q2/8/8/8/1Q6 w - - 0 1", "b5d3", "Qb5d3");
+}
+
+/// More disambiguation than needed is still read.
+#[test]
+fn over_disambiguated() {
+    let pos = Position::from_fen("4k3/8/8/2N1N3/8/8/8/4K3 w - - 0 1").unwrap();
+    let mv = uci_move(&pos, "c5d3");
+    assert!(pos.parse_san("Nc5d3").unwrap() == mv);
+    assert!(pos.parse_san("Nc5xd3").unwrap() == mv);
+    assert_eq!(pos.to_san(mv).unwrap(), "Ncd3");
+}
+
+#[test]
+fn promotion() {
+    let fen = "3r4/4P3/8/8/8/8/k7/4K3 w - - 0 1";
+    assert_san(fen, "e7e8q", "e8=Q");
+    assert_san(fen, "e7d8n", "exd8=N");
+    let pos = Position::from_fen(fen).unwrap();
+    assert!(pos.parse_san("e8Q").unwrap() == uci_move(&pos, "e7e8q"));
+    assert!(pos.parse_san("e8=R").unwrap() == uci_move(&pos, "e7e8r"));
+    assert!(pos.parse_san("exd8B").unwrap() == uci_move(&pos, "e7d8b"));
+}
+
+#[test]
+fn castling() {
+    for fen in ["r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1"] {
+        let pos = Position::from_fen(fen).unwrap();
+        for (short, long) in [("O-O", "O-O-O"), ("0-0", "0-0-0")] {
+            let (short, long) = (pos.parse_san(short).unwrap(), pos.parse_san(long).unwrap());
+            assert!(short.is_castle() && long.is_castle());
+            assert_eq!(pos.to_san(short).unwrap(), "O-O");
+            assert_eq!(pos.to_san(long).unwrap(), "O-O-O");
+        }
+    }
+}
+
+#[test]
+fn check_and_mate() {
+    assert_san("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8", "Ra8+");
+    assert_san("rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2", "d8h4", "Qh4#");
+    // The suffixes are optional when reading
+    let pos = Position::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+    assert!(pos.parse_san("Ra8").unwrap() == uci_move(&pos, "a1a8"));
+    assert!(pos.parse_san("Ra8#").unwrap() == uci_move(&pos, "a1a8"));
+}
+
+#[test]
+fn errors() {
+    let pos = Position::from_fen("rnbqkbnr/ppp1pppp/8/3p4/3P4/5N2/PPP1PPPP/RNBQKB1R w KQkq - 0 3").unwrap();
+    assert!(matches!(pos.parse_san("Nd2"), Err(Error::IllegalMove(_))), "ambiguous");
+    assert!(matches!(pos.parse_san("Ke2"), Err(Error::IllegalMove(_))), "king blocked by its pawn");
+    assert!(matches!(pos.parse_san("O-O"), Err(Error::IllegalMove(_))), "castling through pieces");
+    assert!(matches!(pos.parse_san("e9"), Err(Error::Parse(_))));
+    assert!(matches!(pos.parse_san("N"), Err(Error::Parse(_))));
+}
@@ -0,0 +1,57 @@
+use std::env;
+
+use crab_gambit::moves::MoveList;
+use crab_gambit::syzygy::{Tablebases, WDL_DRAW, WDL_LOSS, WDL_WIN};
+use crab_gambit::{generate_legal_moves, Position};
+
+/// The tables under `SYZYGY_PATH`, or `None` to skip the test when there are
+/// no 3-piece tables to probe.
+fn tablebases() -> Option<Tablebases> {
+    let Ok(path) = env::var("SYZYGY_PATH") else {
+        eprintln!("SYZYGY_PATH is not set, skipping");
+        return None;
+    };
+    let tablebases = Tablebases::new(&path);
+    if tablebases.max_pieces() < 3 {
+        eprintln!("No 3-piece tables in {}, skipping", path);
+        return None;
+    }
+    Some(tablebases)
+}
+
+#[test]
+fn probe_wdl() {
+    let Some(tablebases) = tablebases() else { return };
+    for (fen, wdl) in [
+        ("8/8/8/4k3/8/8/8/R3K3 w - - 0 1", WDL_WIN),
+        ("8/8/8/4k3/8/8/8/R3K3 b - - 0 1", WDL_LOSS),
+        ("8/8/8/4k3/8/8/8/N3K3 w - - 0 1", WDL_DRAW),
+        ("8/8/8/8/8/8/4P3/4K2k w - - 0 1", WDL_WIN),
+        ("8/8/8/8/8/1k6/p7/K7 w - - 0 1", WDL_DRAW)
+    ] {
+        let mut pos = Position::from_fen(fen).unwrap();
+        assert_eq!(tablebases.probe_wdl(&mut pos), Some(wdl), "{}", fen);
+    }
+}
+
+#[test]
+fn probe_dtz() {
+    let Some(tablebases) = tablebases() else { return };
+    // Mate in one, and a lone rook mating from far away
+    let mut pos = Position::from_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1").unwrap();
+    assert_eq!(tablebases.probe_dtz(&mut pos), Some(1));
+    let mut pos = Position::from_fen("8/8/8/4k3/8/8/8/3RK3 w - - 0 1").unwrap();
+    let dtz = tablebases.probe_dtz(&mut pos).unwrap();
+    assert!(dtz > 1 && dtz <= 100, "dtz {}", dtz);
+
+    // Rook moves next to the black king throw the win away
+    let mut moves = MoveList::new();
+    generate_legal_moves(&mut moves, &mut pos).unwrap();
+    let kept = tablebases.filter_root_moves(&mut pos, &moves, true).unwrap();
+    assert!(!kept.is_empty() && kept.len() < moves.len());
+    for mv in kept {
+        pos.do_move(mv).unwrap();
+        assert_eq!(tablebases.probe_wdl(&mut pos), Some(WDL_LOSS), "{}", mv);
+        pos.undo_move().unwrap();
+    }
+}